@@ -0,0 +1,17 @@
+/* Source of hello.elf, built with:
+ * gcc -Os -static -nostdlib -fno-asynchronous-unwind-tables -fno-pie -no-pie -Wl,--build-id=none \
+ *     -Wl,-z,noseparate-code -Wl,-z,norelro -Wl,-N -o hello.elf hello.c
+ */
+static const char message[] = "Hello from an ELF\n";
+int counter;
+int table[4] = {1, 2, 3, 4};
+long sys_write(long fd, const void *buf, long len) {
+    long ret;
+    __asm__ volatile ("syscall" : "=a"(ret) : "a"(1), "D"(fd), "S"(buf), "d"(len) : "rcx", "r11", "memory");
+    return ret;
+}
+void _start(void) {
+    counter = table[2];
+    sys_write(1, message, sizeof message - 1);
+    __asm__ volatile ("mov $60, %eax; xor %edi, %edi; syscall");
+}
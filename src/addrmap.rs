@@ -0,0 +1,194 @@
+//! Where the image sits in the CPU's address space: a load address, or a map
+//! of file ranges onto address ranges, possibly in switched banks.
+
+use std::fs;
+
+use parse;
+
+/// A run of the file mapped at an address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    /// file offset of the first byte
+    pub offset : usize,
+    pub len : usize,
+    /// address of the first byte
+    pub address : u64,
+    /// bank the region is switched into, if the map is banked
+    pub bank : Option<u32>,
+}
+
+impl Region {
+    fn contains_offset(&self, offset : usize) -> bool {
+        offset >= self.offset && offset - self.offset < self.len
+    }
+}
+
+/// Mapping between file offsets and CPU addresses. With no regions, the two
+/// are the same.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddressMap {
+    regions : Vec<Region>,
+}
+
+impl AddressMap {
+    /// Addresses are file offsets.
+    pub fn new() -> AddressMap { AddressMap::default() }
+
+    /// The whole file loaded at `base`.
+    pub fn flat(base : u64) -> AddressMap {
+        AddressMap { regions : vec![Region { offset : 0, len : usize::MAX, address : base, bank : None }] }
+    }
+
+    /// Add a region; regions may share addresses but not file bytes.
+    pub fn add(&mut self, region : Region) -> Result<(), String> {
+        if region.len == 0 {
+            return Err(format!("the region at 0x{:x} is empty", region.offset));
+        }
+        let last = match region.offset.checked_add(region.len - 1) {
+            Some(last) => last,
+            None => return Err(format!("the region at 0x{:x} runs past the end of the file offsets", region.offset)),
+        };
+        if region.address.checked_add((region.len - 1) as u64).is_none() {
+            return Err(format!("the region at 0x{:x} runs past the end of the address space", region.address));
+        }
+        if let Some(r) = self.regions.iter().find(|r| r.contains_offset(region.offset) || r.contains_offset(last)
+                                                     || region.contains_offset(r.offset)) {
+            return Err(format!("file range 0x{:x}-0x{:x} overlaps 0x{:x}-0x{:x}",
+                               region.offset, last, r.offset, r.offset + (r.len - 1)));
+        }
+        self.regions.push(region);
+        Ok(())
+    }
+
+    /// Read a map: one region per line, as the first and last file offsets, the
+    /// address of the first, and optionally a bank number, for instance
+    /// `0x4000-0x7fff $8000 1`. Blank lines and `#` comments are skipped.
+    pub fn parse(text : &str) -> Result<AddressMap, String> {
+        let mut map = AddressMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let fail = |e : String| format!("line {}: {}", n + 1, e);
+            let fields : Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 || fields.len() > 3 {
+                return Err(fail(format!("expected a file range, an address and maybe a bank, not \"{}\"", line)));
+            }
+            let (first, last) = match fields[0].find('-') {
+                Some(i) => (&fields[0][..i], &fields[0][i + 1..]),
+                None => return Err(fail(format!("\"{}\" is not a range like 0x0000-0x3fff", fields[0]))),
+            };
+            let first = parse::parse_usize(first).map_err(fail)?;
+            let last = parse::parse_usize(last).map_err(fail)?;
+            if last < first {
+                return Err(fail(format!("range 0x{:x}-0x{:x} runs backwards", first, last)));
+            }
+            let address = parse::parse_number(fields[1]).map_err(fail)?;
+            let bank = match fields.get(2) {
+                Some(b) => Some(parse::parse_u32(b).map_err(fail)?),
+                None => None,
+            };
+            let len = (last - first).checked_add(1)
+                .ok_or_else(|| fail(format!("range 0x{:x}-0x{:x} is too long", first, last)))?;
+            map.add(Region { offset : first, len, address, bank }).map_err(fail)?;
+        }
+        Ok(map)
+    }
+
+    /// Read a map from a file.
+    pub fn load(path : &str) -> Result<AddressMap, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        AddressMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn is_identity(&self) -> bool { self.regions.is_empty() }
+
+    pub fn regions(&self) -> &[Region] { &self.regions }
+
+    /// The address, and bank, of the byte at a file offset. A flat map may
+    /// run past the end of the address space; bytes there have no address.
+    pub fn address_of(&self, offset : usize) -> Option<(u64, Option<u32>)> {
+        if self.regions.is_empty() { return Some((offset as u64, None)); }
+        let r = self.regions.iter().find(|r| r.contains_offset(offset))?;
+        r.address.checked_add((offset - r.offset) as u64).map(|address| (address, r.bank))
+    }
+
+    /// The file offset of an address: in the given bank, or else the first
+    /// region that holds it.
+    pub fn offset_of(&self, address : u64, bank : Option<u32>) -> Option<usize> {
+        if self.regions.is_empty() { return Some(address as usize); }
+        self.regions.iter()
+            .filter(|r| bank.is_none() || r.bank == bank)
+            .find(|r| address >= r.address && address - r.address < r.len as u64)
+            .map(|r| r.offset + (address - r.address) as usize)
+    }
+
+    /// An offset as it should be shown: its address, with the bank in front
+    /// (`2:0x8000`), or the bare offset after `@` if it isn't mapped.
+    pub fn format(&self, offset : usize) -> String {
+        match self.address_of(offset) {
+            Some((address, Some(bank))) => format!("{}:0x{:x}", bank, address),
+            Some((address, None)) => format!("0x{:x}", address),
+            None => format!("@0x{:x}", offset),
+        }
+    }
+
+    /// The file offset for an address expression typed in by the user, in
+    /// which `.` is the address of `here`. A bank can be given before a colon.
+    pub fn parse_address(&self, text : &str, here : usize) -> Result<usize, String> {
+        let (bank, expr) = match text.find(':') {
+            Some(i) => (Some(parse::parse_u32(text[..i].trim())?), &text[i + 1..]),
+            None => (None, text),
+        };
+        let (current, current_bank) = self.address_of(here).unwrap_or((here as u64, None));
+        let address = parse::eval_expr(expr, current)?;
+        // Prefer the bank we're in when none is given.
+        let found = match bank {
+            Some(_) => self.offset_of(address, bank),
+            None => self.offset_of(address, current_bank).or_else(|| self.offset_of(address, None)),
+        };
+        found.ok_or_else(|| format!("0x{:x} is not in the address map", address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_maps() {
+        let id = AddressMap::new();
+        assert_eq!(Some((0x123, None)), id.address_of(0x123));
+        assert_eq!("0x123", id.format(0x123));
+        assert_eq!(Ok(0x110), id.parse_address(".+10h", 0x100));
+
+        let flat = AddressMap::flat(0xf8000);
+        assert_eq!("0xf8010", flat.format(0x10));
+        assert_eq!(Some(0x10), flat.offset_of(0xf8010, None));
+        assert_eq!(None, flat.offset_of(0x10, None));
+        assert!(flat.parse_address("0x10", 0).is_err());
+
+        let map = AddressMap::parse("# banked\n0x0000-0x3fff $c000\n0x4000-0x7fff $8000 1 # bank 1\n\
+                                     0x8000-0xbfff $8000 2\n").unwrap();
+        assert_eq!(3, map.regions().len());
+        assert_eq!("0xc010", map.format(0x10));
+        assert_eq!("2:0x8010", map.format(0x8010));
+        assert_eq!("@0xc000", map.format(0xc000));
+        assert_eq!(Some(0x4000), map.offset_of(0x8000, None));
+        assert_eq!(Some(0x8000), map.offset_of(0x8000, Some(2)));
+        assert_eq!(Ok(0x8020), map.parse_address(".+0x10", 0x8010));
+        assert_eq!(Ok(0x4020), map.parse_address("1:.+0x10", 0x8010));
+        assert_eq!(Ok(0x10), map.parse_address("$c010", 0x8010));
+
+        assert!(AddressMap::parse("0x0000-0x3fff $c000\n0x3000-0x4fff 0").is_err());
+        assert!(AddressMap::parse("0x0000 $c000").is_err());
+        assert!(AddressMap::parse("0x10-0x0 $c000").is_err());
+
+        // Nothing wraps around the top of the address space.
+        let top = AddressMap::flat(u64::MAX - 0xf);
+        assert_eq!(Some((u64::MAX, None)), top.address_of(0xf));
+        assert_eq!(None, top.address_of(0x10));
+        assert_eq!("@0x10", top.format(0x10));
+        assert!(AddressMap::parse("0x0-0xffffffffffffffff 0").is_err());
+        assert!(AddressMap::parse("0x0-0x1 0xffffffffffffffff").is_err());
+    }
+}
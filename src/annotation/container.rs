@@ -0,0 +1,241 @@
+//! Executable containers: ELF and PE/COFF images, whose headers say where
+//! their sections and segments are and what their symbols are called.
+
+use goblin::elf::{self, Elf};
+use goblin::pe::{self, PE};
+
+use annotation::{Annotation, AnnotationEngine, AnnotationStore, EngineError, Parameter, Params, Progress};
+
+pub struct ContainerAnnotation {
+    start : usize,
+    end : usize,
+    type_str : &'static str,
+    comments : String,
+}
+
+impl Annotation for ContainerAnnotation {
+    fn span(&self) -> (usize, usize) { (self.start, self.end) }
+    fn comments(&self) -> &str { self.comments.as_str() }
+    fn type_str(&self) -> &str { self.type_str }
+    fn confidence(&self) -> u8 { 255 }
+}
+
+/// The kinds of container recognised.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContainerKind {
+    Elf,
+    Pe,
+}
+
+impl ContainerKind {
+    /// Which container, if any, starts at the beginning of `data`.
+    pub fn detect(data : &[u8]) -> Option<ContainerKind> {
+        if data.starts_with(b"\x7fELF") {
+            Some(ContainerKind::Elf)
+        } else if data.starts_with(b"MZ") && PE::parse(data).is_ok() {
+            Some(ContainerKind::Pe)
+        } else {
+            None
+        }
+    }
+}
+
+/// Collects annotations for a container found at `base` in the data,
+/// dropping any that fall outside it.
+struct Builder {
+    base : usize,
+    len : usize,
+    annotations : Vec<Box<dyn Annotation>>,
+}
+
+impl Builder {
+    /// Annotate `len` bytes at `offset` into the container; a point if `len` is 0.
+    fn add(&mut self, offset : u64, len : u64, type_str : &'static str, comments : String) {
+        if offset >= self.len as u64 { return; }
+        let start = self.base + offset as usize;
+        let end = self.base + offset.saturating_add(len.max(1)).min(self.len as u64) as usize;
+        self.annotations.push(Box::new(ContainerAnnotation { start, end, type_str, comments }));
+    }
+}
+
+fn size_str(n : u64) -> String {
+    format!("{} byte{}", n, if n == 1 { "" } else { "s" })
+}
+
+fn elf_annotations(elf : &Elf, b : &mut Builder, symbols : bool) {
+    let h = &elf.header;
+    b.add(0, h.e_ehsize as u64, "Header",
+          format!("ELF{} {} {} {}, entry 0x{:x}", if elf.is_64 { 64 } else { 32 },
+                  if elf.little_endian { "little endian" } else { "big endian" },
+                  elf::header::machine_to_str(h.e_machine), elf::header::et_to_str(h.e_type), h.e_entry));
+    if h.e_phnum > 0 {
+        b.add(h.e_phoff, h.e_phnum as u64 * h.e_phentsize as u64, "Header",
+              format!("program headers: {} segments", h.e_phnum));
+    }
+    if h.e_shnum > 0 {
+        b.add(h.e_shoff, h.e_shnum as u64 * h.e_shentsize as u64, "Header",
+              format!("section headers: {} sections", h.e_shnum));
+    }
+    for ph in &elf.program_headers {
+        if ph.p_filesz == 0 { continue; }
+        let flags : String = [(ph.is_read(), 'R'), (ph.is_write(), 'W'), (ph.is_executable(), 'X')].iter()
+            .map(|&(set, c)| if set { c } else { '-' }).collect();
+        let kind = elf::program_header::pt_to_str(ph.p_type).trim_start_matches("PT_");
+        b.add(ph.p_offset, ph.p_filesz, "Segment",
+              format!("{} {} at 0x{:x} ({} in memory)", kind, flags, ph.p_vaddr, size_str(ph.p_memsz)));
+    }
+    for sh in &elf.section_headers {
+        if sh.sh_type == elf::section_header::SHT_NOBITS || sh.sh_size == 0 { continue; }
+        let name = elf.shdr_strtab.get_at(sh.sh_name).unwrap_or("?");
+        let comments = if sh.sh_addr != 0 {
+            format!("{} at 0x{:x} ({})", name, sh.sh_addr, size_str(sh.sh_size))
+        } else {
+            format!("{} ({})", name, size_str(sh.sh_size))
+        };
+        b.add(sh.sh_offset, sh.sh_size, "Section", comments);
+    }
+    if !symbols { return; }
+    let tables = [(&elf.syms, &elf.strtab), (&elf.dynsyms, &elf.dynstrtab)];
+    for &(syms, strtab) in &tables {
+        for sym in syms.iter() {
+            let name = match strtab.get_at(sym.st_name) {
+                Some(n) if !n.is_empty() => n,
+                _ => continue,
+            };
+            // Only symbols for bytes that are in the file can be placed.
+            let sh = match elf.section_headers.get(sym.st_shndx) {
+                Some(sh) if sym.st_shndx != 0 && sh.sh_type != elf::section_header::SHT_NOBITS => sh,
+                _ => continue,
+            };
+            if sym.st_value < sh.sh_addr || sym.st_value - sh.sh_addr >= sh.sh_size { continue; }
+            let offset = match sh.sh_offset.checked_add(sym.st_value - sh.sh_addr) {
+                Some(o) => o,
+                None => continue,
+            };
+            b.add(offset, 0, "Symbol",
+                  format!("{} ({} at 0x{:x}, {})", name, elf::sym::type_to_str(sym.st_type()).to_lowercase(),
+                          sym.st_value, size_str(sym.st_size)));
+        }
+    }
+}
+
+fn pe_annotations(pe : &PE, b : &mut Builder, symbols : bool) {
+    let coff = &pe.header.coff_header;
+    let pe_start = pe.header.dos_header.pe_pointer as u64;
+    b.add(0, 0x40, "Header", "MZ header".to_string());
+    // the PE signature and COFF header, then the optional header
+    let headers = 4 + 20 + coff.size_of_optional_header as u64;
+    b.add(pe_start, headers, "Header",
+          format!("PE{} {}, image base 0x{:x}, entry 0x{:x}", if pe.is_64 { "32+" } else { "32" },
+                  pe::header::machine_to_str(coff.machine), pe.image_base,
+                  pe.image_base.wrapping_add(pe.entry as u64)));
+    b.add(pe_start + headers, coff.number_of_sections as u64 * 40, "Header",
+          format!("section table: {} sections", coff.number_of_sections));
+    for s in &pe.sections {
+        if s.size_of_raw_data == 0 { continue; }
+        b.add(s.pointer_to_raw_data as u64, s.size_of_raw_data as u64, "Section",
+              format!("{} at 0x{:x} ({})", s.name().unwrap_or("?"), pe.image_base.wrapping_add(s.virtual_address as u64),
+                      size_str(s.virtual_size as u64)));
+    }
+    if !symbols { return; }
+    // File offset of a relative virtual address, if it's in a section's data.
+    let offset_of = |rva : u64| pe.sections.iter()
+        .find(|s| rva >= s.virtual_address as u64 && rva - (s.virtual_address as u64) < s.size_of_raw_data as u64)
+        .map(|s| s.pointer_to_raw_data as u64 + (rva - s.virtual_address as u64));
+    // The image base comes from the file too, so the addresses may not fit.
+    let address_of = |rva : u64| pe.image_base.checked_add(rva);
+    if let (Some(offset), Some(address)) = (offset_of(pe.entry as u64), address_of(pe.entry as u64)) {
+        b.add(offset, 0, "Symbol", format!("entry point (0x{:x})", address));
+    }
+    for e in &pe.exports {
+        if let (Some(name), Some(offset), Some(address)) = (e.name, offset_of(e.rva as u64), address_of(e.rva as u64)) {
+            b.add(offset, 0, "Symbol", format!("{} (export at 0x{:x})", name, address));
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ContainerAnnotationEngine;
+
+static PARAMS : [Parameter; 2] = [
+    Parameter { name : "offset", default : "0", help : "where the container starts in the image" },
+    Parameter { name : "symbols", default : "true", help : "mark symbols: true or false" },
+];
+
+impl AnnotationEngine for ContainerAnnotationEngine {
+    fn id(&self) -> &'static str { "container" }
+
+    fn description(&self) -> &'static str { "ELF and PE images: headers, segments, sections and symbols" }
+
+    fn parameters(&self) -> &'static [Parameter] { &PARAMS }
+
+    fn build_annotations(&self, raw_data : &[u8], params : &Params, progress : &mut Progress)
+                         -> Result<AnnotationStore, EngineError> {
+        params.check(&PARAMS)?;
+        let base = params.get_usize("offset", 0)?;
+        let symbols = params.get("symbols", true)?;
+        let data = raw_data.get(base..).unwrap_or(&[]);
+        let mut builder = Builder { base, len : data.len(), annotations : Vec::new() };
+        progress.update(0, data.len())?;
+        // Anything else is a raw image, with nothing to say about it.
+        match ContainerKind::detect(data) {
+            Some(ContainerKind::Elf) => {
+                let elf = Elf::parse(data).map_err(|e| EngineError::Failed(format!("bad ELF image: {}", e)))?;
+                elf_annotations(&elf, &mut builder, symbols);
+            },
+            Some(ContainerKind::Pe) => {
+                let pe = PE::parse(data).map_err(|e| EngineError::Failed(format!("bad PE image: {}", e)))?;
+                pe_annotations(&pe, &mut builder, symbols);
+            },
+            None => {},
+        }
+        let mut annotations = AnnotationStore::new("Container");
+        annotations.extend(builder.annotations);
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static HELLO : &[u8] = include_bytes!("../../sample_binaries/hello.elf");
+
+    #[test]
+    fn elf_container() {
+        assert_eq!(Some(ContainerKind::Elf), ContainerKind::detect(HELLO));
+        let engine = ContainerAnnotationEngine;
+        let store = engine.build_default(HELLO).unwrap();
+        let at = |pos : usize, type_str : &str| store.query(pos).iter().find(|a| a.type_str() == type_str)
+            .map(|a| (a.span(), a.comments().to_string()));
+        assert_eq!(Some(((0, 64), "ELF64 little endian X86_64 EXEC, entry 0x4000b8".to_string())), at(0, "Header"));
+        assert_eq!(Some(((0xb0, 0x120), "LOAD RWX at 0x4000b0 (120 bytes in memory)".to_string())),
+                   at(0xb0, "Segment"));
+        assert_eq!(Some(((0xb0, 0xe4), ".text at 0x4000b0 (52 bytes)".to_string())), at(0xb0, "Section"));
+        assert_eq!(Some(((0x120, 0x147), ".comment (39 bytes)".to_string())), at(0x120, "Section"));
+        assert_eq!(Some(((0xb8, 0xb9), "_start (func at 0x4000b8, 44 bytes)".to_string())), at(0xb8, "Symbol"));
+        assert_eq!(Some(((0x110, 0x111), "table (object at 0x400110, 16 bytes)".to_string())), at(0x110, "Symbol"));
+        // counter is in .bss, with no bytes in the file
+        assert!(store.iter().all(|a| !a.comments().starts_with("counter")));
+
+        let params = Params::parse("symbols=false").unwrap();
+        let bare = engine.build_annotations(HELLO, &params, &mut Progress::none()).unwrap();
+        assert!(bare.iter().all(|a| a.type_str() != "Symbol"));
+        // found part way into a larger image
+        let mut padded = vec![0xff; 0x100];
+        padded.extend_from_slice(HELLO);
+        let params = Params::parse("offset=0x100").unwrap();
+        let moved = engine.build_annotations(&padded, &params, &mut Progress::none()).unwrap();
+        assert_eq!(store.len(), moved.len());
+        assert_eq!((0x1b0, 0x1e4), moved.query(0x1b0).iter().find(|a| a.type_str() == "Section").unwrap().span());
+
+        // A section past the end of the address space places no symbols.
+        let mut bad = HELLO.to_vec();
+        bad[0x310..0x318].copy_from_slice(&0xffff_ffff_ffff_fff0u64.to_le_bytes()); // .text's offset
+        let store = engine.build_default(&bad).unwrap();
+        assert!(store.iter().all(|a| !a.comments().starts_with("_start")));
+
+        assert_eq!(None, ContainerKind::detect(&padded));
+        assert!(engine.build_default(&padded).unwrap().is_empty());
+    }
+}
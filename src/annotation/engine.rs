@@ -0,0 +1,192 @@
+//! Everything an annotation engine needs besides the data: its parameters,
+//! a way to report progress and be cancelled, and a way to fail.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use annotation::AnnotationEngine;
+use parse::parse_usize;
+use annotation::container::ContainerAnnotationEngine;
+use annotation::entropy::EntropyAnnotationEngine;
+use annotation::fill::FillAnnotationEngine;
+use annotation::magic::MagicAnnotationEngine;
+use annotation::strings::CStringAnnotationEngine;
+
+#[derive(Debug, PartialEq)]
+pub enum EngineError {
+    /// A parameter the engine doesn't know about.
+    UnknownParam(String),
+    /// A parameter with a value the engine can't use: (name, reason).
+    BadParam(String, String),
+    /// The run was cancelled through its `Progress`.
+    Cancelled,
+    Failed(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EngineError::UnknownParam(ref name) => write!(f, "unknown parameter '{}'", name),
+            EngineError::BadParam(ref name, ref why) => write!(f, "bad value for '{}': {}", name, why),
+            EngineError::Cancelled => write!(f, "cancelled"),
+            EngineError::Failed(ref why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+/// Description of a parameter an engine accepts.
+pub struct Parameter {
+    pub name : &'static str,
+    pub default : &'static str,
+    pub help : &'static str,
+}
+
+/// Named parameter values for an engine run, as given by the user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params {
+    values : BTreeMap<String, String>,
+}
+
+impl Params {
+    pub fn new() -> Params { Params::default() }
+
+    pub fn set(&mut self, name : &str, value : &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    /// Parse whitespace separated `name=value` assignments.
+    pub fn parse(text : &str) -> Result<Params, EngineError> {
+        let mut params = Params::new();
+        for word in text.split_whitespace() {
+            match word.find('=') {
+                Some(i) => params.set(&word[..i], &word[i + 1..]),
+                None => return Err(EngineError::BadParam(word.to_string(), "expected name=value".to_string())),
+            }
+        }
+        Ok(params)
+    }
+
+    pub fn get_str(&self, name : &str) -> Option<&str> {
+        self.values.get(name).map(|v| v.as_str())
+    }
+
+    /// The value of a parameter, or the default if it isn't set.
+    pub fn get<T : FromStr>(&self, name : &str, default : T) -> Result<T, EngineError> {
+        match self.values.get(name) {
+            Some(v) => v.parse().map_err(|_| EngineError::BadParam(name.to_string(), format!("can't parse '{}'", v))),
+            None => Ok(default),
+        }
+    }
+
+    /// The value of a size or count parameter, which may be written in hex
+    /// or with a size suffix (see `parse::parse_number`), or the default.
+    pub fn get_usize(&self, name : &str, default : usize) -> Result<usize, EngineError> {
+        match self.values.get(name) {
+            Some(v) => parse_usize(v).map_err(|e| EngineError::BadParam(name.to_string(), e)),
+            None => Ok(default),
+        }
+    }
+
+    pub fn names(&self) -> ::std::collections::btree_map::Keys<'_, String, String> {
+        self.values.keys()
+    }
+
+    /// Fail if any parameter isn't one of those given.
+    pub fn check(&self, known : &[Parameter]) -> Result<(), EngineError> {
+        match self.values.keys().find(|k| !known.iter().any(|p| p.name == k.as_str())) {
+            Some(k) => Err(EngineError::UnknownParam(k.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Progress reporting and cancellation for an engine run.
+pub struct Progress<'a> {
+    report : Option<&'a mut dyn FnMut(usize, usize)>,
+    cancel : Option<&'a AtomicBool>,
+}
+
+impl<'a> Progress<'a> {
+    /// Neither report progress nor allow cancellation.
+    pub fn none() -> Progress<'a> {
+        Progress { report : None, cancel : None }
+    }
+
+    /// Call `report` with (work done, total work) as the engine runs, and stop
+    /// early once `cancel` is set.
+    pub fn new(report : &'a mut dyn FnMut(usize, usize), cancel : &'a AtomicBool) -> Progress<'a> {
+        Progress { report : Some(report), cancel : Some(cancel) }
+    }
+
+    /// Note that `done` of `total` units of work are complete. Returns
+    /// `EngineError::Cancelled` if the run should stop.
+    pub fn update(&mut self, done : usize, total : usize) -> Result<(), EngineError> {
+        if let Some(ref mut report) = self.report {
+            report(done, total);
+        }
+        match self.cancel {
+            Some(c) if c.load(Ordering::Relaxed) => Err(EngineError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Every available engine.
+pub fn engines() -> Vec<Box<dyn AnnotationEngine>> {
+    vec![
+        Box::new(CStringAnnotationEngine::default()),
+        Box::new(EntropyAnnotationEngine::default()),
+        Box::new(FillAnnotationEngine::default()),
+        Box::new(ContainerAnnotationEngine),
+        Box::new(MagicAnnotationEngine),
+    ]
+}
+
+pub fn engine_by_id(id : &str) -> Option<Box<dyn AnnotationEngine>> {
+    engines().into_iter().find(|e| e.id() == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params() {
+        let p = Params::parse("min_len=7  encoding=utf16le").unwrap();
+        assert_eq!(Ok(7usize), p.get("min_len", 5));
+        assert_eq!(Ok(7), p.get_usize("min_len", 5));
+        assert_eq!(Ok(0x100), Params::parse("window=0x100").unwrap().get_usize("window", 5));
+        assert_eq!(Ok(2.0f32), p.get("low", 2.0));
+        assert_eq!(Some("utf16le"), p.get_str("encoding"));
+        assert!(p.get::<u8>("encoding", 0).is_err());
+        assert!(Params::parse("oops").is_err());
+
+        let known = [Parameter { name : "min_len", default : "5", help : "" }];
+        assert_eq!(Err(EngineError::UnknownParam("encoding".to_string())), p.check(&known));
+
+        let cancel = AtomicBool::new(false);
+        let mut calls = 0;
+        {
+            let mut report = |_, _| calls += 1;
+            let mut progress = Progress::new(&mut report, &cancel);
+            assert!(progress.update(1, 2).is_ok());
+            cancel.store(true, Ordering::Relaxed);
+            assert_eq!(Err(EngineError::Cancelled), progress.update(2, 2));
+        }
+        assert_eq!(2, calls);
+    }
+
+    #[test]
+    fn engine_ids_are_unique() {
+        let all = engines();
+        for (i, e) in all.iter().enumerate() {
+            assert!(all[i + 1..].iter().all(|o| o.id() != e.id()));
+            assert!(engine_by_id(e.id()).is_some());
+        }
+    }
+}
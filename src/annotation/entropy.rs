@@ -0,0 +1,206 @@
+//! Shannon entropy of the data, as a rough guide to what kind of data lives
+//! where: padding and tables are low, code and text are in the middle, and
+//! compressed or encrypted data is close to 8 bits per byte.
+
+use annotation::{Annotation, AnnotationEngine, AnnotationStore, EngineError, Parameter, Params, Progress};
+
+/// Entropy, in bits per byte, of the given byte counts over `total` bytes.
+fn entropy_of(counts : &[usize; 256], total : usize) -> f32 {
+    if total == 0 { return 0.0; }
+    let total = total as f64;
+    let h : f64 = counts.iter().filter(|&&c| c > 0).map(|&c| {
+        let p = c as f64 / total;
+        -p * p.log2()
+    }).sum();
+    h as f32
+}
+
+/// Entropy of each consecutive block of `block` bytes (the last may be short).
+pub fn block_entropy(data : &[u8], block : usize) -> Vec<f32> {
+    data.chunks(block.max(1)).map(|chunk| {
+        let mut counts = [0usize; 256];
+        for &b in chunk { counts[b as usize] += 1; }
+        entropy_of(&counts, chunk.len())
+    }).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntropyLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl EntropyLevel {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EntropyLevel::Low => "Low Entropy",
+            EntropyLevel::Medium => "Medium Entropy",
+            EntropyLevel::High => "High Entropy",
+        }
+    }
+}
+
+pub struct EntropyAnnotation {
+    start : usize,
+    end : usize,
+    level : EntropyLevel,
+    comments : String,
+}
+
+impl Annotation for EntropyAnnotation {
+    fn span(&self) -> (usize, usize) { (self.start, self.end) }
+    fn comments(&self) -> &str { self.comments.as_str() }
+    fn type_str(&self) -> &str { self.level.name() }
+    fn confidence(&self) -> u8 { 128 }
+}
+
+#[derive(Clone)]
+pub struct EntropyAnnotationEngine {
+    /// bytes over which each entropy value is measured
+    window : usize,
+    /// distance between the starts of successive windows
+    step : usize,
+    /// entropies below this are low, in bits per byte
+    low : f32,
+    /// entropies above this are high, in bits per byte
+    high : f32,
+}
+
+impl Default for EntropyAnnotationEngine {
+    fn default() -> EntropyAnnotationEngine {
+        EntropyAnnotationEngine::with_window(256, 256)
+    }
+}
+
+static PARAMS : [Parameter; 4] = [
+    Parameter { name : "window", default : "256", help : "bytes over which each value is measured" },
+    Parameter { name : "step", default : "256", help : "bytes between successive windows" },
+    Parameter { name : "low", default : "2.0", help : "bits per byte below which entropy is low" },
+    Parameter { name : "high", default : "7.0", help : "bits per byte above which entropy is high" },
+];
+
+impl EntropyAnnotationEngine {
+    /// An engine measuring `window` bytes at a time, sliding `step` bytes each time.
+    pub fn with_window(window : usize, step : usize) -> EntropyAnnotationEngine {
+        EntropyAnnotationEngine { window : window.max(1), step : step.max(1), low : 2.0, high : 7.0 }
+    }
+
+    pub fn with_thresholds(mut self, low : f32, high : f32) -> EntropyAnnotationEngine {
+        self.low = low;
+        self.high = high;
+        self
+    }
+
+    fn level(&self, h : f32) -> EntropyLevel {
+        if h < self.low { EntropyLevel::Low }
+        else if h > self.high { EntropyLevel::High }
+        else { EntropyLevel::Medium }
+    }
+
+    /// This engine's settings with any parameters applied.
+    fn with_params(&self, params : &Params) -> Result<EntropyAnnotationEngine, EngineError> {
+        params.check(&PARAMS)?;
+        let engine = EntropyAnnotationEngine::with_window(params.get_usize("window", self.window)?,
+                                                          params.get_usize("step", self.step)?);
+        Ok(engine.with_thresholds(params.get("low", self.low)?, params.get("high", self.high)?))
+    }
+
+    /// Entropy of the window starting at each multiple of the step.
+    pub fn sliding_entropy(&self, raw_data : &[u8]) -> Vec<f32> {
+        self.sliding_entropy_with_progress(raw_data, &mut Progress::none()).unwrap()
+    }
+
+    fn sliding_entropy_with_progress(&self, raw_data : &[u8], progress : &mut Progress)
+                                     -> Result<Vec<f32>, EngineError> {
+        let mut values = Vec::new();
+        let mut counts = [0usize; 256];
+        let (mut lo, mut hi) = (0, 0); // the window currently counted
+        let mut start = 0;
+        while start < raw_data.len() {
+            if values.len() % 256 == 0 {
+                progress.update(start, raw_data.len())?;
+            }
+            let end = (start + self.window).min(raw_data.len());
+            // With a step longer than the window, the new window may start
+            // past everything counted so far.
+            while lo < start && lo < hi { counts[raw_data[lo] as usize] -= 1; lo += 1; }
+            if lo < start { lo = start; hi = start; }
+            while hi < end { counts[raw_data[hi] as usize] += 1; hi += 1; }
+            values.push(entropy_of(&counts, hi - lo));
+            start += self.step;
+        }
+        Ok(values)
+    }
+}
+
+impl AnnotationEngine for EntropyAnnotationEngine {
+    fn id(&self) -> &'static str { "entropy" }
+
+    fn description(&self) -> &'static str { "Regions of low, medium and high entropy" }
+
+    fn parameters(&self) -> &'static [Parameter] { &PARAMS }
+
+    fn build_annotations(&self, raw_data : &[u8], params : &Params, progress : &mut Progress)
+                         -> Result<AnnotationStore, EngineError> {
+        let engine = self.with_params(params)?;
+        let mut annotations = AnnotationStore::new("Entropy");
+        // Each step-sized chunk takes the entropy of the window starting there;
+        // runs of chunks at the same level become one region.
+        let values = engine.sliding_entropy_with_progress(raw_data, progress)?;
+        let mut run_start = 0;
+        while run_start < values.len() {
+            let level = engine.level(values[run_start]);
+            let mut run_end = run_start + 1;
+            while run_end < values.len() && engine.level(values[run_end]) == level {
+                run_end += 1;
+            }
+            let run = &values[run_start..run_end];
+            let mean = run.iter().sum::<f32>() / run.len() as f32;
+            let min = run.iter().cloned().fold(8.0f32, f32::min);
+            let max = run.iter().cloned().fold(0.0f32, f32::max);
+            annotations.insert(Box::new(EntropyAnnotation {
+                start : run_start * engine.step,
+                end : (run_end * engine.step).min(raw_data.len()),
+                level,
+                comments : format!("{:.2} bits/byte (min {:.2}, max {:.2})", mean, min, max),
+            }));
+            run_start = run_end;
+        }
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entropy_regions() {
+        // 1k of fill, then 1k in which every byte value appears equally often
+        let mut data = vec![0xffu8; 1024];
+        data.extend((0..1024).map(|i| (i * 167) as u8));
+        let values = block_entropy(&data, 256);
+        assert_eq!(8, values.len());
+        assert_eq!(0.0, values[0]);
+        assert!((values[7] - 8.0).abs() < 1e-4);
+
+        let engine = EntropyAnnotationEngine::with_window(256, 128);
+        let sliding = engine.sliding_entropy(&data);
+        assert_eq!(16, sliding.len());
+        assert_eq!(block_entropy(&data[128..384], 256)[0], sliding[1]);
+        // windows with gaps between them
+        let sparse = EntropyAnnotationEngine::with_window(64, 256).sliding_entropy(&data);
+        assert_eq!(8, sparse.len());
+        assert_eq!(block_entropy(&data[1280..1344], 64)[0], sparse[5]);
+
+        let store = EntropyAnnotationEngine::default().build_default(&data).unwrap();
+        let spans : Vec<((usize, usize), &str)> = store.iter().map(|a| (a.span(), a.type_str())).collect();
+        assert_eq!(vec![((0, 1024), "Low Entropy"), ((1024, 2048), "High Entropy")], spans);
+
+        let params = Params::parse("window=512 step=512 high=8.5").unwrap();
+        let store = engine.build_annotations(&data, &params, &mut Progress::none()).unwrap();
+        let spans : Vec<((usize, usize), &str)> = store.iter().map(|a| (a.span(), a.type_str())).collect();
+        assert_eq!(vec![((0, 1024), "Low Entropy"), ((1024, 2048), "Medium Entropy")], spans);
+    }
+}
@@ -0,0 +1,188 @@
+//! Padding and fill regions: runs of one byte value, or of a short repeated
+//! pattern, such as the `FF` left in unprogrammed EPROM space.
+
+use annotation::{Annotation, AnnotationEngine, AnnotationStore, EngineError, Parameter, Params, Progress};
+
+/// A run of repeated data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub start : usize,
+    /// length of the run in bytes; may end part way through the pattern
+    pub len : usize,
+    pub pattern : Vec<u8>,
+}
+
+/// Free space taken up by one fill pattern across the whole image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FillTotal {
+    pub pattern : Vec<u8>,
+    pub bytes : usize,
+    pub regions : usize,
+}
+
+pub fn pattern_str(pattern : &[u8]) -> String {
+    pattern.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+}
+
+/// Total the fills by pattern, largest first.
+pub fn summarize(fills : &[Fill]) -> Vec<FillTotal> {
+    let mut totals : Vec<FillTotal> = Vec::new();
+    for f in fills {
+        match totals.iter_mut().find(|t| t.pattern == f.pattern) {
+            Some(t) => { t.bytes += f.len; t.regions += 1; },
+            None => totals.push(FillTotal { pattern : f.pattern.clone(), bytes : f.len, regions : 1 }),
+        }
+    }
+    totals.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.pattern.cmp(&b.pattern)));
+    totals
+}
+
+pub struct FillAnnotation {
+    start : usize,
+    end : usize,
+    comments : String,
+}
+
+impl Annotation for FillAnnotation {
+    fn span(&self) -> (usize, usize) { (self.start, self.end) }
+    fn comments(&self) -> &str { self.comments.as_str() }
+    fn type_str(&self) -> &str { "Fill" }
+    fn confidence(&self) -> u8 { 255 }
+}
+
+#[derive(Clone)]
+pub struct FillAnnotationEngine {
+    /// shortest run, in bytes, worth reporting
+    min_len : usize,
+    /// longest pattern, in bytes, to look for
+    max_period : usize,
+}
+
+impl Default for FillAnnotationEngine {
+    fn default() -> FillAnnotationEngine {
+        FillAnnotationEngine::new(16, 4)
+    }
+}
+
+static PARAMS : [Parameter; 2] = [
+    Parameter { name : "min_len", default : "16", help : "shortest run, in bytes, to report" },
+    Parameter { name : "max_period", default : "4", help : "longest repeated pattern, in bytes" },
+];
+
+impl FillAnnotationEngine {
+    pub fn new(min_len : usize, max_period : usize) -> FillAnnotationEngine {
+        FillAnnotationEngine { min_len : min_len.max(2), max_period : max_period.max(1) }
+    }
+
+    /// Find every fill region, in order.
+    pub fn find_fills(&self, raw_data : &[u8]) -> Vec<Fill> {
+        self.find_fills_with_progress(raw_data, &mut Progress::none()).unwrap()
+    }
+
+    fn find_fills_with_progress(&self, raw_data : &[u8], progress : &mut Progress)
+                                -> Result<Vec<Fill>, EngineError> {
+        let mut fills = Vec::new();
+        let mut idx = 0;
+        let mut next_report = 0;
+        while idx < raw_data.len() {
+            if idx >= next_report {
+                progress.update(idx, raw_data.len())?;
+                next_report = idx + 0x10000;
+            }
+            // Try each period, keeping the longest run; shorter periods win ties,
+            // so a run of 00 is never reported as a run of 00 00.
+            let mut best = (0, 0); // (period, run length)
+            // Starting anywhere before the last `period` bytes of a run only
+            // finds the same run again, shorter; skip those starts.
+            let mut skip_to = usize::MAX;
+            for period in 1..=self.max_period {
+                if idx + period > raw_data.len() { break; }
+                let mut end = idx + period;
+                while end < raw_data.len() && raw_data[end] == raw_data[end - period] {
+                    end += 1;
+                }
+                skip_to = skip_to.min(end - period + 1);
+                if end - idx > best.1 && end - idx >= 2 * period {
+                    best = (period, end - idx);
+                }
+            }
+            let (period, len) = best;
+            if period > 0 && len >= self.min_len {
+                fills.push(Fill { start : idx, len, pattern : raw_data[idx..idx + period].to_vec() });
+                idx += len;
+            } else {
+                idx = skip_to.max(idx + 1);
+            }
+        }
+        Ok(fills)
+    }
+}
+
+impl AnnotationEngine for FillAnnotationEngine {
+    fn id(&self) -> &'static str { "fill" }
+
+    fn description(&self) -> &'static str { "Padding: runs of a repeated byte or short pattern" }
+
+    fn parameters(&self) -> &'static [Parameter] { &PARAMS }
+
+    fn build_annotations(&self, raw_data : &[u8], params : &Params, progress : &mut Progress)
+                         -> Result<AnnotationStore, EngineError> {
+        params.check(&PARAMS)?;
+        let engine = FillAnnotationEngine::new(params.get_usize("min_len", self.min_len)?,
+                                                params.get_usize("max_period", self.max_period)?);
+        let mut annotations = AnnotationStore::new("Fill");
+        let fills = engine.find_fills_with_progress(raw_data, progress)?;
+        let totals = summarize(&fills);
+        for f in &fills {
+            let total = totals.iter().find(|t| t.pattern == f.pattern).unwrap();
+            annotations.insert(Box::new(FillAnnotation {
+                start : f.start,
+                end : f.start + f.len,
+                comments : format!("fill {} x {} bytes ({} bytes in {} region{} in all)",
+                                   pattern_str(&f.pattern), f.len, total.bytes, total.regions,
+                                   if total.regions == 1 { "" } else { "s" }),
+            }));
+        }
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_regions() {
+        let mut data = vec![1, 2, 3];
+        data.extend(vec![0xff; 20]);
+        data.extend(vec![4, 5]);
+        data.extend([0x55, 0xaa].iter().cycle().take(33));
+        data.extend(vec![0; 8]); // too short
+        data.extend(vec![6]);
+        data.extend(vec![0xff; 16]);
+        let engine = FillAnnotationEngine::default();
+        let fills = engine.find_fills(&data);
+        assert_eq!(vec![
+            Fill { start : 3, len : 20, pattern : vec![0xff] },
+            Fill { start : 25, len : 33, pattern : vec![0x55, 0xaa] },
+            Fill { start : 67, len : 16, pattern : vec![0xff] },
+        ], fills);
+        let totals = summarize(&fills);
+        assert_eq!(FillTotal { pattern : vec![0xff], bytes : 36, regions : 2 }, totals[0]);
+        assert_eq!(FillTotal { pattern : vec![0x55, 0xaa], bytes : 33, regions : 1 }, totals[1]);
+
+        let store = engine.build_default(&data).unwrap();
+        assert_eq!(3, store.len());
+        let params = Params::parse("min_len=8").unwrap();
+        assert_eq!(4, engine.build_annotations(&data, &params, &mut Progress::none()).unwrap().len());
+        assert_eq!("fill 55 AA x 33 bytes (33 bytes in 1 region in all)", store.query(30)[0].comments());
+
+        // A short run of one pattern can lead into a long run of another.
+        let mut data = vec![1, 1, 1];
+        data.extend([2, 1].iter().cycle().take(20));
+        assert_eq!(vec![Fill { start : 2, len : 21, pattern : vec![1, 2] }], engine.find_fills(&data));
+        // Runs shorter than the minimum are passed over in one go.
+        let engine = FillAnnotationEngine::new(1 << 21, 4);
+        assert!(engine.find_fills(&vec![0xff; 1 << 20]).is_empty());
+    }
+}
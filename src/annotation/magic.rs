@@ -0,0 +1,641 @@
+//! Files embedded in the image, found by their magic numbers: compressed
+//! streams, pictures, archives, executables and filesystems. Where the header
+//! says how big the object is, the annotation covers all of it.
+
+use std::fs;
+
+use goblin::elf::Elf;
+use goblin::pe::PE;
+
+use annotation::{Annotation, AnnotationEngine, AnnotationStore, EngineError, Parameter, Params, Progress};
+use project::crc32;
+
+/// What a decoder makes of a header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Found {
+    /// size of the whole object, if the header gives it
+    pub len : Option<u64>,
+    pub confidence : u8,
+    pub comments : String,
+}
+
+impl Found {
+    fn new(len : Option<u64>, comments : String) -> Option<Found> {
+        Some(Found { len, confidence : 255, comments })
+    }
+}
+
+/// Checks and describes the object starting at the beginning of the slice,
+/// or rejects it as a false match.
+pub type Decoder = fn(&[u8]) -> Option<Found>;
+
+/// A magic number, and where it sits in the object it marks.
+#[derive(Clone)]
+pub struct Signature {
+    pub name : String,
+    pub magic : Vec<u8>,
+    /// offset of the magic number from the start of the object
+    pub offset : usize,
+    pub decode : Option<Decoder>,
+}
+
+impl Signature {
+    /// Read signatures from text: one per line, as the magic number in hex,
+    /// optionally `@offset` into the object, and a name, for instance
+    /// `53454741 @0x100 Mega Drive header`. Blank lines and `#` comments are skipped.
+    pub fn parse_list(text : &str) -> Result<Vec<Signature>, String> {
+        let mut sigs = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let fail = |e : String| format!("line {}: {}", n + 1, e);
+            let mut fields = line.splitn(2, char::is_whitespace);
+            let hex = fields.next().unwrap();
+            let mut rest = fields.next().unwrap_or("").trim();
+            if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+                return Err(fail(format!("\"{}\" is not a magic number of whole hex bytes", hex)));
+            }
+            let magic = (0..hex.len()).step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| fail(format!("\"{}\" is not a magic number of whole hex bytes", hex)))?;
+            let mut offset = 0;
+            if rest.starts_with('@') {
+                let mut parts = rest.splitn(2, char::is_whitespace);
+                offset = ::parse::parse_usize(&parts.next().unwrap()[1..]).map_err(fail)?;
+                rest = parts.next().unwrap_or("").trim();
+            }
+            if rest.is_empty() {
+                return Err(fail(format!("signature {} needs a name", hex)));
+            }
+            sigs.push(Signature { name : rest.to_string(), magic, offset, decode : None });
+        }
+        Ok(sigs)
+    }
+}
+
+/// A signature found in the image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub start : usize,
+    /// bytes covered: the whole object if its size is known, else its magic number
+    pub len : usize,
+    pub name : String,
+    pub confidence : u8,
+    pub comments : String,
+}
+
+fn le16(d : &[u8], o : usize) -> Option<u16> { d.get(o..o + 2).map(|b| u16::from_le_bytes([b[0], b[1]])) }
+fn be16(d : &[u8], o : usize) -> Option<u16> { d.get(o..o + 2).map(|b| u16::from_be_bytes([b[0], b[1]])) }
+fn le32(d : &[u8], o : usize) -> Option<u32> { d.get(o..o + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])) }
+fn be32(d : &[u8], o : usize) -> Option<u32> { d.get(o..o + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])) }
+fn le64(d : &[u8], o : usize) -> Option<u64> {
+    d.get(o..o + 8).map(|b| b.iter().rev().fold(0, |a, &x| (a << 8) | x as u64))
+}
+
+/// Text from a fixed size, NUL padded field.
+fn field_str(d : &[u8], o : usize, len : usize) -> String {
+    let f = d.get(o..o + len).unwrap_or(&[]);
+    let f = &f[..f.iter().position(|&b| b == 0).unwrap_or(f.len())];
+    String::from_utf8_lossy(f).trim_end().to_string()
+}
+
+fn size_str(n : u64) -> String {
+    if n >= 1 << 20 && n.is_multiple_of(1 << 20) {
+        format!("{} MiB", n >> 20)
+    } else if n >= 1 << 10 && n.is_multiple_of(1 << 10) {
+        format!("{} KiB", n >> 10)
+    } else {
+        format!("{} bytes", n)
+    }
+}
+
+fn gzip(d : &[u8]) -> Option<Found> {
+    let flags = *d.get(3)?;
+    if d.get(2) != Some(&8) || flags & 0xe0 != 0 || d.len() < 10 { return None; }
+    let mut comments = "gzip, deflate".to_string();
+    if flags & 0x08 != 0 {
+        // the original file name follows any extra field
+        let at = if flags & 0x04 != 0 { 12 + le16(d, 10)? as usize } else { 10 };
+        comments += &format!(", name \"{}\"", field_str(d, at, 256));
+    }
+    let mtime = le32(d, 4)?;
+    if mtime != 0 { comments += &format!(", modified {}", mtime); }
+    Found::new(None, comments)
+}
+
+fn zlib(d : &[u8]) -> Option<Found> {
+    let (cmf, flg) = (*d.first()?, *d.get(1)?);
+    if !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) || flg & 0x20 != 0 { return None; }
+    // The first deflate block can't be of the reserved type, and if stored,
+    // its length is followed by its complement.
+    let block = *d.get(2)?;
+    match (block >> 1) & 3 {
+        3 => return None,
+        0 if le16(d, 3)? != !le16(d, 5)? => return None,
+        _ => {},
+    }
+    let level = ["fastest", "fast", "default", "best"][(flg >> 6) as usize];
+    // Two bytes are easily matched by chance.
+    Some(Found { len : None, confidence : 128, comments : format!("zlib, deflate, {} compression", level) })
+}
+
+fn lzma(d : &[u8]) -> Option<Found> {
+    let dict = le32(d, 1)?;
+    let unpacked = le64(d, 5)?;
+    if !dict.is_power_of_two() || dict < 1 << 12 || (unpacked != u64::MAX && unpacked > 1 << 40) { return None; }
+    let size = if unpacked == u64::MAX { "size unknown".to_string() } else { format!("unpacks to {}", size_str(unpacked)) };
+    Found::new(None, format!("LZMA, {} dictionary, {}", size_str(dict as u64), size))
+}
+
+fn xz(d : &[u8]) -> Option<Found> {
+    let check = match (d.get(6)?, d.get(7)?) {
+        (0, 0) => "no check",
+        (0, 1) => "CRC32",
+        (0, 4) => "CRC64",
+        (0, 10) => "SHA-256",
+        _ => return None,
+    };
+    Found::new(None, format!("xz, {}", check))
+}
+
+fn bzip2(d : &[u8]) -> Option<Found> {
+    let level = *d.get(3)?;
+    if !(b'1'..=b'9').contains(&level) || d.get(4..10)? != [0x31, 0x41, 0x59, 0x26, 0x53, 0x59] { return None; }
+    Found::new(None, format!("bzip2, {}00k blocks", level as char))
+}
+
+fn sevenzip(d : &[u8]) -> Option<Found> {
+    let (major, minor) = (*d.get(6)?, *d.get(7)?);
+    let len = 32u64.checked_add(le64(d, 12)?)?.checked_add(le64(d, 20)?)?;
+    Found::new(Some(len), format!("7-Zip archive, version {}.{}", major, minor))
+}
+
+fn png(d : &[u8]) -> Option<Found> {
+    if d.get(12..16)? != b"IHDR" { return None; }
+    let colour = match d.get(25)? {
+        0 => "grey",
+        2 => "RGB",
+        3 => "palette",
+        4 => "grey and alpha",
+        6 => "RGBA",
+        _ => return None,
+    };
+    let comments = format!("PNG image, {}x{}, {}-bit {}", be32(d, 16)?, be32(d, 20)?, d.get(24)?, colour);
+    // Follow the chunks to the end.
+    let mut pos = 8usize;
+    let mut len = None;
+    while let (Some(n), Some(kind)) = (be32(d, pos), d.get(pos + 4..pos + 8)) {
+        pos = pos.checked_add(12 + n as usize)?;
+        if kind == b"IEND" { len = Some(pos as u64); break; }
+    }
+    Found::new(len, comments)
+}
+
+fn jpeg(d : &[u8]) -> Option<Found> {
+    let mut comments = "JPEG image".to_string();
+    let mut pos = 2;
+    // Walk the header segments to the start of the scan.
+    loop {
+        if *d.get(pos)? != 0xff { return Found::new(None, comments); }
+        let marker = *d.get(pos + 1)?;
+        match marker {
+            0xff => { pos += 1; continue; },
+            0x01 | 0xd0..=0xd7 => { pos += 2; continue; },
+            _ => {},
+        }
+        let seg = be16(d, pos + 2)? as usize;
+        if let 0xc0..=0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf = marker {
+            comments = format!("JPEG image, {}x{}", be16(d, pos + 7)?, be16(d, pos + 5)?);
+        }
+        pos += 2 + seg;
+        if marker == 0xda { break; }
+    }
+    // The entropy coded data runs to the end of image marker.
+    let mut len = None;
+    while let Some(i) = d.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0xff)) {
+        pos += i;
+        match d.get(pos + 1) {
+            Some(0xd9) => { len = Some(pos as u64 + 2); break; },
+            Some(_) => pos += 1,
+            None => break,
+        }
+    }
+    Found::new(len, comments)
+}
+
+fn zip(d : &[u8]) -> Option<Found> {
+    let flags = le16(d, 6)?;
+    let method = match le16(d, 8)? {
+        0 => "stored".to_string(),
+        8 => "deflate".to_string(),
+        9 => "deflate64".to_string(),
+        12 => "bzip2".to_string(),
+        14 => "LZMA".to_string(),
+        93 => "zstd".to_string(),
+        99 => "AES encrypted".to_string(),
+        m => format!("method {}", m),
+    };
+    let (packed, unpacked) = (le32(d, 18)? as u64, le32(d, 22)? as u64);
+    let (name_len, extra_len) = (le16(d, 26)? as u64, le16(d, 28)? as u64);
+    let name = field_str(d, 30, name_len as usize);
+    if name.is_empty() { return None; }
+    let header = 30 + name_len + extra_len;
+    // With a data descriptor, the sizes come after the data instead.
+    if flags & 0x08 != 0 {
+        return Found::new(Some(header), format!("ZIP entry \"{}\", {}", name, method));
+    }
+    Found::new(Some(header + packed), format!("ZIP entry \"{}\", {}, {} packed to {}", name, method,
+                                              size_str(unpacked), size_str(packed)))
+}
+
+fn elf(d : &[u8]) -> Option<Found> {
+    if !matches!(d.get(4..7)?, [1..=2, 1..=2, 1]) { return None; }
+    let elf = Elf::parse(d).ok()?;
+    let h = &elf.header;
+    // The header fields aren't checked, so nonsense sizes mean it isn't an ELF.
+    let mut len = h.e_shoff.checked_add(h.e_shnum as u64 * h.e_shentsize as u64)?
+        .max(h.e_phoff.checked_add(h.e_phnum as u64 * h.e_phentsize as u64)?);
+    for ph in &elf.program_headers { len = len.max(ph.p_offset.checked_add(ph.p_filesz)?); }
+    for sh in &elf.section_headers {
+        if sh.sh_type != ::goblin::elf::section_header::SHT_NOBITS {
+            len = len.max(sh.sh_offset.checked_add(sh.sh_size)?);
+        }
+    }
+    Found::new(Some(len), format!("ELF{} {} {}", if elf.is_64 { 64 } else { 32 },
+                                  ::goblin::elf::header::machine_to_str(h.e_machine),
+                                  ::goblin::elf::header::et_to_str(h.e_type)))
+}
+
+fn mz(d : &[u8]) -> Option<Found> {
+    if let Ok(pe) = PE::parse(d) {
+        let len = pe.sections.iter().map(|s| s.pointer_to_raw_data as u64 + s.size_of_raw_data as u64).max();
+        return Found::new(len, format!("PE{} executable, {}, {} sections", if pe.is_64 { "32+" } else { "32" },
+                                       ::goblin::pe::header::machine_to_str(pe.header.coff_header.machine),
+                                       pe.sections.len()));
+    }
+    // Otherwise a DOS executable, if its header adds up.
+    let (last, pages, header) = (le16(d, 2)? as u64, le16(d, 4)? as u64, le16(d, 8)? as u64);
+    if pages == 0 || last >= 512 || header < 2 || header * 16 > pages * 512 { return None; }
+    let len = (pages - 1) * 512 + if last == 0 { 512 } else { last };
+    Some(Found { len : Some(len), confidence : 128, comments : format!("DOS executable, {}", size_str(len)) })
+}
+
+fn uimage(d : &[u8]) -> Option<Found> {
+    let mut header = d.get(..64)?.to_vec();
+    let crc = be32(&header, 4)?;
+    header[4..8].copy_from_slice(&[0; 4]);
+    if crc32(&header) != crc { return None; }
+    let size = be32(d, 12)? as u64;
+    let kind = match d[30] {
+        1 => "standalone program",
+        2 => "kernel",
+        3 => "ramdisk",
+        4 => "multi-file image",
+        5 => "firmware",
+        6 => "script",
+        7 => "filesystem",
+        8 => "device tree",
+        _ => "image",
+    };
+    let arch = match d[29] {
+        2 => "ARM",
+        3 => "x86",
+        5 => "MIPS",
+        7 => "PowerPC",
+        15 => "SuperH",
+        22 => "ARM64",
+        26 => "RISC-V",
+        _ => "other CPU",
+    };
+    let comp = match d[31] {
+        0 => "uncompressed",
+        1 => "gzip",
+        2 => "bzip2",
+        3 => "LZMA",
+        4 => "LZO",
+        5 => "LZ4",
+        6 => "zstd",
+        _ => "unknown compression",
+    };
+    Found::new(Some(64 + size), format!("uImage \"{}\", {} {}, {}, {}, load 0x{:x}, entry 0x{:x}",
+                                        field_str(d, 32, 32), arch, kind, comp, size_str(size),
+                                        be32(d, 16)?, be32(d, 20)?))
+}
+
+fn squashfs(d : &[u8]) -> Option<Found> {
+    let (block, block_log, major, minor) = (le32(d, 12)?, le16(d, 22)?, le16(d, 28)?, le16(d, 30)?);
+    if major != 4 || block_log >= 32 || block != 1 << block_log { return None; }
+    let comp = match le16(d, 20)? {
+        1 => "gzip",
+        2 => "LZMA",
+        3 => "LZO",
+        4 => "xz",
+        5 => "LZ4",
+        6 => "zstd",
+        _ => return None,
+    };
+    let used = le64(d, 40)?;
+    Found::new(Some(used), format!("squashfs {}.{}, {}, {} blocks, {} inodes, {}", major, minor, comp,
+                                   size_str(block as u64), le32(d, 4)?, size_str(used)))
+}
+
+fn cramfs(d : &[u8], big : bool) -> Option<Found> {
+    if d.get(16..32)? != b"Compressed ROMFS" { return None; }
+    let size = if big { be32(d, 4)? } else { le32(d, 4)? } as u64;
+    Found::new(Some(size), format!("cramfs \"{}\", {}, {} endian", field_str(d, 48, 16), size_str(size),
+                                   if big { "big" } else { "little" }))
+}
+
+fn cramfs_le(d : &[u8]) -> Option<Found> { cramfs(d, false) }
+
+fn cramfs_be(d : &[u8]) -> Option<Found> { cramfs(d, true) }
+
+/// Follow JFFS2 nodes, and the erased space between them, to the end of the filesystem.
+fn jffs2(d : &[u8], big : bool) -> Option<Found> {
+    let read16 = if big { be16 } else { le16 };
+    let read32 = if big { be32 } else { le32 };
+    let magic = read16(d, 0)?;
+    let (mut pos, mut end, mut nodes) = (0usize, 0usize, 0);
+    loop {
+        while d.get(pos..pos + 4) == Some(&[0xff; 4]) { pos += 4; }
+        let kind = match (read16(d, pos), read16(d, pos + 2)) {
+            (Some(m), Some(k)) if m == magic => k,
+            _ => break,
+        };
+        let len = read32(d, pos + 4)? as usize;
+        if !matches!(kind, 0xe001 | 0xe002 | 0x2003 | 0x2004 | 0x2006 | 0xe008 | 0xe009) || len < 12 { break; }
+        nodes += 1;
+        end = pos + len;
+        pos += (len + 3) & !3;
+    }
+    if nodes == 0 { return None; }
+    Found::new(Some(end as u64), format!("JFFS2 filesystem, {} endian, {} node{}, {}",
+                                         if big { "big" } else { "little" }, nodes,
+                                         if nodes == 1 { "" } else { "s" }, size_str(end as u64)))
+}
+
+fn jffs2_le(d : &[u8]) -> Option<Found> { jffs2(d, false) }
+
+fn jffs2_be(d : &[u8]) -> Option<Found> { jffs2(d, true) }
+
+fn fat(d : &[u8]) -> Option<Found> {
+    if !matches!((d.first()?, d.get(2)?), (0xeb, 0x90) | (0xe9, _)) { return None; }
+    let sector = le16(d, 11)? as u64;
+    let cluster = *d.get(13)?;
+    let (reserved, fats, media) = (le16(d, 14)?, *d.get(16)?, *d.get(21)?);
+    if !matches!(sector, 512 | 1024 | 2048 | 4096) || !cluster.is_power_of_two() || reserved == 0
+        || !(1..=2).contains(&fats) || (media < 0xf8 && media != 0xf0) {
+        return None;
+    }
+    let sectors = match le16(d, 19)? { 0 => le32(d, 32)? as u64, n => n as u64 };
+    let fat32 = le16(d, 22)? == 0;
+    let (label, kind) = if fat32 { (field_str(d, 71, 11), field_str(d, 82, 8)) }
+                        else { (field_str(d, 43, 11), field_str(d, 54, 8)) };
+    let kind = if kind.starts_with("FAT") { kind } else { "FAT".to_string() };
+    Found::new(Some(sectors * sector), format!("{} boot sector, label \"{}\", {} sectors of {} bytes, {} per cluster",
+                                               kind, label, sectors, sector, cluster))
+}
+
+fn option_rom(d : &[u8]) -> Option<Found> {
+    let size = *d.get(2)? as usize * 512;
+    let image = d.get(..size)?;
+    // The entry point is a jump, or a far return for ROMs with nothing to run.
+    if size == 0 || !matches!(d[3], 0xe9 | 0xeb | 0xcb) { return None; }
+    // Padding of a repeated pattern can sum to zero too.
+    if (1..=16).any(|p| image[p..].iter().zip(image).all(|(a, b)| a == b)) { return None; }
+    let sum_ok = image.iter().fold(0u8, |s, &b| s.wrapping_add(b)) == 0;
+    let mut comments = format!("x86 option ROM, {}, checksum {}", size_str(size as u64), if sum_ok { "good" } else { "bad" });
+    if let Some(p) = le16(d, 0x18).map(|p| p as usize) {
+        if d.get(p..p + 4) == Some(b"PCIR") {
+            comments += &format!(", PCI {:04x}:{:04x}", le16(d, p + 4)?, le16(d, p + 6)?);
+        }
+    }
+    Some(Found { len : Some(size as u64), confidence : if sum_ok { 255 } else { 128 }, comments })
+}
+
+fn fdt(d : &[u8]) -> Option<Found> {
+    let (size, version) = (be32(d, 4)? as u64, be32(d, 20)?);
+    if !(1..=17).contains(&version) || size < 40 || be32(d, 8)? as u64 >= size { return None; }
+    Found::new(Some(size), format!("device tree blob, version {}, {}", version, size_str(size)))
+}
+
+fn cpio(d : &[u8]) -> Option<Found> {
+    let hex = |o : usize| d.get(o..o + 8).and_then(|f| ::std::str::from_utf8(f).ok())
+        .and_then(|f| u64::from_str_radix(f, 16).ok());
+    let (file_len, name_len) = (hex(54)?, hex(94)?);
+    let name = field_str(d, 110, name_len as usize);
+    let len = ((110 + name_len + 3) & !3) + ((file_len + 3) & !3);
+    Found::new(Some(len), format!("cpio entry \"{}\", {}", name, size_str(file_len)))
+}
+
+/// The built-in signatures.
+pub fn builtin_signatures() -> Vec<Signature> {
+    let table : [(&str, &[u8], usize, Decoder); 27] = [
+        ("gzip", &[0x1f, 0x8b], 0, gzip),
+        ("zlib", &[0x78, 0x01], 0, zlib),
+        ("zlib", &[0x78, 0x5e], 0, zlib),
+        ("zlib", &[0x78, 0x9c], 0, zlib),
+        ("zlib", &[0x78, 0xda], 0, zlib),
+        ("LZMA", &[0x5d, 0x00, 0x00], 0, lzma),
+        ("xz", &[0xfd, b'7', b'z', b'X', b'Z', 0x00], 0, xz),
+        ("bzip2", b"BZh", 0, bzip2),
+        ("7-Zip", &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c], 0, sevenzip),
+        ("PNG", &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a], 0, png),
+        ("JPEG", &[0xff, 0xd8, 0xff], 0, jpeg),
+        ("ZIP", &[b'P', b'K', 0x03, 0x04], 0, zip),
+        ("ELF", &[0x7f, b'E', b'L', b'F'], 0, elf),
+        ("MZ", b"MZ", 0, mz),
+        ("uImage", &[0x27, 0x05, 0x19, 0x56], 0, uimage),
+        ("squashfs", b"hsqs", 0, squashfs),
+        ("cramfs", &[0x45, 0x3d, 0xcd, 0x28], 0, cramfs_le),
+        ("cramfs", &[0x28, 0xcd, 0x3d, 0x45], 0, cramfs_be),
+        ("JFFS2", &[0x85, 0x19], 0, jffs2_le),
+        ("JFFS2", &[0x19, 0x85], 0, jffs2_be),
+        ("FAT", &[0x55, 0xaa], 510, fat),
+        ("option ROM", &[0x55, 0xaa], 0, option_rom),
+        ("device tree", &[0xd0, 0x0d, 0xfe, 0xed], 0, fdt),
+        ("cpio", b"070701", 0, cpio),
+        ("cpio", b"070702", 0, cpio),
+        ("LZ4", &[0x04, 0x22, 0x4d, 0x18], 0, |_| Found::new(None, "LZ4 frame".to_string())),
+        ("zstd", &[0x28, 0xb5, 0x2f, 0xfd], 0, |_| Found::new(None, "Zstandard frame".to_string())),
+    ];
+    table.iter().map(|&(name, magic, offset, decode)| Signature {
+        name : name.to_string(), magic : magic.to_vec(), offset, decode : Some(decode),
+    }).collect()
+}
+
+/// Find every signature in the data. A signature isn't looked for again
+/// inside an object it has already matched, so the nodes of a filesystem
+/// don't each show up on their own.
+pub fn scan(data : &[u8], sigs : &[Signature], progress : &mut Progress) -> Result<Vec<Match>, EngineError> {
+    let mut by_first : Vec<Vec<usize>> = vec![Vec::new(); 256];
+    for (i, s) in sigs.iter().enumerate() {
+        if let Some(&b) = s.magic.first() { by_first[b as usize].push(i); }
+    }
+    let mut free_from = vec![0usize; sigs.len()];
+    let mut matches = Vec::new();
+    for pos in 0..data.len() {
+        if pos & 0xffff == 0 { progress.update(pos, data.len())?; }
+        for &i in &by_first[data[pos] as usize] {
+            let s = &sigs[i];
+            if pos < s.offset || pos - s.offset < free_from[i] || !data[pos..].starts_with(&s.magic) { continue; }
+            let start = pos - s.offset;
+            let found = match s.decode {
+                Some(decode) => match decode(&data[start..]) {
+                    Some(f) => f,
+                    None => continue,
+                },
+                None => Found { len : None, confidence : 255, comments : s.name.clone() },
+            };
+            let least = (s.offset + s.magic.len()) as u64;
+            let len = found.len.unwrap_or(least).max(least).min((data.len() - start) as u64) as usize;
+            free_from[i] = start + len;
+            matches.push(Match { start, len, name : s.name.clone(), confidence : found.confidence,
+                                 comments : found.comments });
+        }
+    }
+    matches.sort_by_key(|m| m.start);
+    Ok(matches)
+}
+
+pub struct MagicAnnotation {
+    start : usize,
+    end : usize,
+    type_str : String,
+    confidence : u8,
+    comments : String,
+}
+
+impl Annotation for MagicAnnotation {
+    fn span(&self) -> (usize, usize) { (self.start, self.end) }
+    fn comments(&self) -> &str { self.comments.as_str() }
+    fn type_str(&self) -> &str { self.type_str.as_str() }
+    fn confidence(&self) -> u8 { self.confidence }
+}
+
+#[derive(Clone, Default)]
+pub struct MagicAnnotationEngine;
+
+static PARAMS : [Parameter; 1] = [
+    Parameter { name : "signatures", default : "",
+                help : "file of more signatures: lines of \"hex-magic [@offset] name\"" },
+];
+
+impl AnnotationEngine for MagicAnnotationEngine {
+    fn id(&self) -> &'static str { "magic" }
+
+    fn description(&self) -> &'static str { "Embedded files: compressed data, images, archives, executables, filesystems" }
+
+    fn parameters(&self) -> &'static [Parameter] { &PARAMS }
+
+    fn build_annotations(&self, raw_data : &[u8], params : &Params, progress : &mut Progress)
+                         -> Result<AnnotationStore, EngineError> {
+        params.check(&PARAMS)?;
+        let mut sigs = builtin_signatures();
+        if let Some(path) = params.get_str("signatures").filter(|p| !p.is_empty()) {
+            let more = fs::read_to_string(path).map_err(|e| e.to_string())
+                .and_then(|text| Signature::parse_list(&text))
+                .map_err(|e| EngineError::BadParam("signatures".to_string(), format!("{}: {}", path, e)))?;
+            sigs.extend(more);
+        }
+        let mut annotations = AnnotationStore::new("Signatures");
+        for m in scan(raw_data, &sigs, progress)? {
+            annotations.insert(Box::new(MagicAnnotation {
+                start : m.start,
+                end : m.start + m.len,
+                type_str : m.name,
+                confidence : m.confidence,
+                comments : m.comments,
+            }));
+        }
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static HELLO : &[u8] = include_bytes!("../../sample_binaries/hello.elf");
+
+    #[test]
+    fn signature_scan() {
+        let mut data = vec![0u8; 0x40];
+        // gzip with a file name
+        data.extend_from_slice(&[0x1f, 0x8b, 8, 0x08, 0, 0, 0, 0, 0, 3]);
+        data.extend_from_slice(b"rom.bin\0");
+        data.resize(0x100, 0);
+        // a PNG: signature, IHDR, IEND (the CRCs aren't checked)
+        data.extend_from_slice(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0, 0, 13]);
+        data.extend_from_slice(b"IHDR\0\0\x01\x40\0\0\0\xc8\x08\x03\0\0\0\0\0\0\0\0\0\0\0IEND\0\0\0\0");
+        data.resize(0x200, 0);
+        // an option ROM of 512 bytes, with a good checksum
+        let rom = data.len();
+        data.extend_from_slice(&[0x55, 0xaa, 1, 0xeb, 0x20]);
+        data.resize(rom + 511, 0);
+        let sum = data[rom..].iter().fold(0u8, |s, &b| s.wrapping_add(b));
+        data.push(sum.wrapping_neg());
+        // a uImage holding 16 bytes
+        let uimage = data.len();
+        let mut header = vec![0x27, 0x05, 0x19, 0x56, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16,
+                              0, 0, 0x80, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 5, 2, 2, 1];
+        header.extend_from_slice(b"Linux-4.4\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        let crc = crc32(&header);
+        header[4..8].copy_from_slice(&crc.to_be_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&[0xaa; 16]);
+        data.resize(0x600, 0);
+        data.extend_from_slice(HELLO);
+        let elf = 0x600;
+
+        let sigs = builtin_signatures();
+        let found = scan(&data, &sigs, &mut Progress::none()).unwrap();
+        let names : Vec<&str> = found.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(vec!["gzip", "PNG", "option ROM", "uImage", "ELF"], names);
+        assert_eq!("gzip, deflate, name \"rom.bin\"", found[0].comments);
+        assert_eq!((0x100, 45), (found[1].start, found[1].len));
+        assert_eq!("PNG image, 320x200, 8-bit palette", found[1].comments);
+        assert_eq!((rom, 512), (found[2].start, found[2].len));
+        assert_eq!("x86 option ROM, 512 bytes, checksum good", found[2].comments);
+        assert_eq!((uimage, 80), (found[3].start, found[3].len));
+        assert_eq!("uImage \"Linux-4.4\", ARM kernel, gzip, 16 bytes, load 0x8000, entry 0x8000", found[3].comments);
+        assert_eq!((elf, HELLO.len()), (found[4].start, found[4].len));
+        assert_eq!("ELF64 X86_64 EXEC", found[4].comments);
+
+        // A broken header is no match.
+        data[uimage + 40] ^= 1;
+        assert!(scan(&data, &sigs, &mut Progress::none()).unwrap().iter().all(|m| m.name != "uImage"));
+
+        let mine = Signature::parse_list("# mine\n53454741 @0x100 Mega Drive header\nfeedface Mach-O\n").unwrap();
+        assert_eq!((vec![0x53, 0x45, 0x47, 0x41], 0x100), (mine[0].magic.clone(), mine[0].offset));
+        assert_eq!("Mach-O", mine[1].name);
+        let mut md = vec![0u8; 0x100];
+        md.extend_from_slice(b"SEGA GENESIS");
+        let found = scan(&md, &mine, &mut Progress::none()).unwrap();
+        assert_eq!((0, 0x104), (found[0].start, found[0].len));
+        assert!(Signature::parse_list("abc name").is_err());
+        assert!(Signature::parse_list("abcd").is_err());
+
+        let store = MagicAnnotationEngine.build_default(HELLO).unwrap();
+        assert_eq!(1, store.len());
+        assert_eq!("ELF", store.query(0)[0].type_str());
+        // Offsets that run off the end of the address space aren't an ELF.
+        let mut bad = HELLO.to_vec();
+        bad[0x48..0x50].copy_from_slice(&0xffff_ffff_ffff_fff0u64.to_le_bytes()); // first segment's offset
+        assert!(MagicAnnotationEngine.build_default(&bad).unwrap().is_empty());
+        let mut bad = HELLO.to_vec();
+        bad[0x310..0x318].copy_from_slice(&0xffff_ffff_ffff_fff0u64.to_le_bytes()); // .text's offset
+        assert!(MagicAnnotationEngine.build_default(&bad).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fill_is_not_an_option_rom() {
+        // 55 aa 55 ... claims 85 sectors, and they sum to zero.
+        let fill : Vec<u8> = [0x55, 0xaa].iter().cycle().take(0x10000).cloned().collect();
+        assert!(MagicAnnotationEngine.build_default(&fill).unwrap().is_empty());
+        let mut pattern = Vec::new();
+        for _ in 0..0x4000 { pattern.extend_from_slice(&[0x55, 0xaa, 0x04, 0xeb]); }
+        assert!(MagicAnnotationEngine.build_default(&pattern).unwrap().is_empty());
+    }
+}
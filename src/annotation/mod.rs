@@ -1,125 +1,204 @@
+pub mod container;
+pub mod engine;
+pub mod entropy;
+pub mod fill;
+pub mod magic;
+pub mod strings;
+pub mod user;
+
+pub use self::engine::{EngineError, Parameter, Params, Progress, engine_by_id, engines};
+pub use self::strings::{CStringAnnotation, CStringAnnotationEngine};
+
 pub trait Annotation {
+    /// The bytes covered, from the first up to but not including the second.
     fn span(&self) -> (usize, usize);
     fn comments(&self) -> &str;
     fn type_str(&self) -> &str;
     fn confidence(&self) -> u8;
 }
 
+/// An annotation held as plain data, such as one loaded from a project file.
+pub struct GenericAnnotation {
+    pub start : usize,
+    pub end : usize,
+    pub type_str : String,
+    pub confidence : u8,
+    pub comments : String,
+}
+
+impl Annotation for GenericAnnotation {
+    fn span(&self) -> (usize, usize) { (self.start, self.end) }
+    fn comments(&self) -> &str { self.comments.as_str() }
+    fn type_str(&self) -> &str { self.type_str.as_str() }
+    fn confidence(&self) -> u8 { self.confidence }
+}
+
 pub trait AnnotationEngine {
-    fn new() -> Self;
-    fn build_annotations(&self, raw_data : &[u8]) -> AnnotationStore;
+    /// Short, stable name, as used on the command line.
+    fn id(&self) -> &'static str;
+    /// One line description, for menus and help.
+    fn description(&self) -> &'static str;
+    /// The parameters `build_annotations` accepts.
+    fn parameters(&self) -> &'static [Parameter];
+    /// Annotate the data. Parameters override the engine's own settings.
+    fn build_annotations(&self, raw_data : &[u8], params : &Params, progress : &mut Progress)
+                         -> Result<AnnotationStore, EngineError>;
+
+    /// Annotate the data with the engine's own settings.
+    fn build_default(&self, raw_data : &[u8]) -> Result<AnnotationStore, EngineError> {
+        self.build_annotations(raw_data, &Params::new(), &mut Progress::none())
+    }
 }
 
+/// A titled collection of annotations, indexed for lookup by position.
+///
+/// Annotations are kept sorted by the start of their span and augmented with
+/// an implicit interval tree (as in Heng Li's cgranges): the node at index `i`
+/// sits at level `i.trailing_ones()`, and `max_end[i]` is the greatest span end
+/// in the subtree below it. Spans are half open: `(start, end)` covers `start`
+/// up to but not including `end`.
 pub struct AnnotationStore {
     v : Vec<Box<dyn Annotation>>,
+    /// greatest span end in the subtree rooted at each node
+    max_end : Vec<usize>,
     title : String,
 }
 
+use std::cmp;
 use std::slice::Iter;
 
+/// Level of the root of the implicit tree over n > 0 nodes.
+fn root_level(n : usize) -> u32 {
+    (usize::BITS - 1) - n.leading_zeros()
+}
+
+/// Parent of node x, which sits at level k.
+fn parent(x : usize, k : u32) -> usize {
+    if (x >> (k + 1)) & 1 == 0 { x + (1 << k) } else { x - (1 << k) }
+}
+
 impl AnnotationStore {
     pub fn new(title : &str) -> AnnotationStore {
-        AnnotationStore { v : Vec::new(), title : String::from(title), }
+        AnnotationStore { v : Vec::new(), max_end : Vec::new(), title : String::from(title), }
     }
+
+    /// Add an annotation. Inserting in order of span start (as the engines do)
+    /// is cheap; an out-of-order insert rebuilds the index.
     pub fn insert(&mut self, a : Box<dyn Annotation>) {
-        self.v.push(a)
+        let start = a.span().0;
+        if self.v.last().is_none_or(|l| l.span().0 <= start) {
+            self.v.push(a);
+            self.index_node(self.v.len() - 1);
+        } else {
+            let pos = self.v.partition_point(|x| x.span().0 <= start);
+            self.v.insert(pos, a);
+            self.reindex();
+        }
+    }
+
+    /// Remove the first annotation with exactly the given span.
+    pub fn remove(&mut self, span : (usize, usize)) -> Option<Box<dyn Annotation>> {
+        let pos = self.v.iter().position(|a| a.span() == span)?;
+        let a = self.v.remove(pos);
+        self.reindex();
+        Some(a)
     }
 
-    pub fn query<'a>(&'a self, point : usize) -> Vec<&'a Box<dyn Annotation>> {
-        let mut v = Vec::new();
-        for a in &self.v {
-            let span = a.span();
-            if (point >= span.0) && (point <= span.1) {
-                v.push(a);
+    /// Add many annotations at once, rebuilding the index a single time.
+    pub fn extend<I : IntoIterator<Item = Box<dyn Annotation>>>(&mut self, iter : I) {
+        self.v.extend(iter);
+        self.v.sort_by_key(|a| a.span().0);
+        self.reindex();
+    }
+
+    /// Fold annotation i into the tree, given that all before it are indexed.
+    fn index_node(&mut self, i : usize) {
+        let n = i + 1;
+        let end = self.v[i].span().1;
+        let mut k = i.trailing_ones();
+        let mut m = end;
+        if k > 0 {
+            // The left subtree is complete; the right one is still empty.
+            m = cmp::max(m, self.max_end[i - (1 << (k - 1))]);
+        }
+        self.max_end.push(m);
+        // Ancestors past the end of the array don't exist yet, but the real
+        // ones above them still cover this node.
+        let mut x = i;
+        while k < root_level(n) {
+            x = parent(x, k);
+            k += 1;
+            if x < n && self.max_end[x] < end {
+                self.max_end[x] = end;
             }
         }
-        v
     }
 
-    pub fn iter(&self) -> Iter<Box<dyn Annotation>> { self.v.iter() }
-}
+    fn reindex(&mut self) {
+        let n = self.v.len();
+        self.max_end.clear();
+        self.max_end.reserve(n);
+        for i in 0..n {
+            self.index_node(i);
+        }
+    }
 
-impl IntoIterator for AnnotationStore {
-    type Item = Box<dyn Annotation>;
-    type IntoIter = ::std::vec::IntoIter<Self::Item>;
-    fn into_iter(self) -> Self::IntoIter { self.v.into_iter() }
-}
+    pub fn len(&self) -> usize { self.v.len() }
 
-pub struct CStringAnnotation {
-    start : usize,
-    end : usize,
-    contents : String,
-}
+    pub fn is_empty(&self) -> bool { self.v.is_empty() }
 
-impl Annotation for CStringAnnotation {
-    fn span(&self) -> (usize, usize) { (self.start, self.end) }
-    fn comments(&self) -> &str { self.contents.as_str() }
-    fn type_str(&self) -> &str { "ASCII String" }
-    fn confidence(&self) -> u8 { 255 }
-}
+    /// All annotations containing the given point.
+    pub fn query(&self, point : usize) -> Vec<&dyn Annotation> {
+        self.query_range(point, point.saturating_add(1))
+    }
 
-pub struct CStringAnnotationEngine { }
+    /// All annotations overlapping the range [start, end), ordered by start.
+    pub fn query_range(&self, start : usize, end : usize) -> Vec<&dyn Annotation> {
+        let mut out = Vec::new();
+        if !self.v.is_empty() {
+            let k = root_level(self.v.len());
+            self.collect_overlaps((1 << k) - 1, k, start, end, &mut out);
+        }
+        out
+    }
 
-static ASCII_LOOKUP : [bool;128] = [
-    false, false, false, false,    false, false, false, false,
-    false, true,  true,  false,    false, true,  false, false,
-    false, false, false, false,    false, false, false, false,
-    false, false, false, false,    false, false, false, false,
+    fn collect_overlaps<'a>(&'a self, x : usize, k : u32, start : usize, end : usize,
+                            out : &mut Vec<&'a dyn Annotation>) {
+        if x >= self.v.len() {
+            // Not a real node; only its left subtree can hold anything.
+            if k > 0 { self.collect_overlaps(x - (1 << (k - 1)), k - 1, start, end, out); }
+            return;
+        }
+        if self.max_end[x] <= start { return; }
+        if k > 0 { self.collect_overlaps(x - (1 << (k - 1)), k - 1, start, end, out); }
+        let span = self.v[x].span();
+        if span.0 >= end { return; } // everything to the right starts later still
+        if span.1 > start { out.push(self.v[x].as_ref()); }
+        if k > 0 { self.collect_overlaps(x + (1 << (k - 1)), k - 1, start, end, out); }
+    }
 
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
+    /// The first annotation starting after the given point.
+    pub fn next_after(&self, point : usize) -> Option<&dyn Annotation> {
+        let idx = self.v.partition_point(|a| a.span().0 <= point);
+        self.v.get(idx).map(|a| a.as_ref())
+    }
 
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
+    /// The last annotation starting before the given point.
+    pub fn prev_before(&self, point : usize) -> Option<&dyn Annotation> {
+        let idx = self.v.partition_point(|a| a.span().0 < point);
+        if idx == 0 { None } else { Some(self.v[idx - 1].as_ref()) }
+    }
 
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  true,
-    true,  true,  true,  true,     true,  true,  true,  false, ];
+    /// Iterate over the annotations in order of span start.
+    pub fn iter(&self) -> Iter<'_, Box<dyn Annotation>> { self.v.iter() }
 
-    
-impl AnnotationEngine for CStringAnnotationEngine {
-    fn new() -> Self {
-        CStringAnnotationEngine {}
-    }
+    pub fn title(&self) -> &str { self.title.as_str() }
+}
 
-    fn build_annotations(&self, raw_data : &[u8]) -> AnnotationStore {
-        let mut annotations = AnnotationStore::new("C Strings");
-        // Iterate through and find null-terminated sequences of ascii
-        // characters longer than 4 chars
-        let min_str_len = 4;
-
-        let mut start : Option<usize> = None;
-        let mut idx = 0;
-        while idx < raw_data.len() {
-            let c = raw_data[idx];
-            if c == 0 {
-                match start {
-                    None => (),
-                    Some(w) if idx-w > min_str_len => {
-                        let v : Vec<u8> = raw_data[w..idx].to_vec();
-                        let a = CStringAnnotation
-                        { start : w,
-                          end : idx+1,
-                         contents : String::from_utf8(v).unwrap() };
-                        start = None;
-                        annotations.insert(Box::new(a));
-                    },
-                    _ => { start = None; }
-                }
-            } else if (c >= 128) || !ASCII_LOOKUP[c as usize] {
-                start = None;
-            } else if start == None {
-                start = Some(idx);
-            }
-            idx = idx + 1;
-        }
-        annotations
-    }
+impl IntoIterator for AnnotationStore {
+    type Item = Box<dyn Annotation>;
+    type IntoIter = ::std::vec::IntoIter<Self::Item>;
+    fn into_iter(self) -> Self::IntoIter { self.v.into_iter() }
 }
 
 #[cfg(test)]
@@ -129,8 +208,8 @@ mod tests {
     #[test]
     fn ascii_annotation_engine() {
         static ASCII_TEST: &'static [u8] = include_bytes!("../../sample_binaries/strings-test.bin");
-        let engine = CStringAnnotationEngine::new();
-        let annotations = engine.build_annotations(ASCII_TEST);
+        let engine = CStringAnnotationEngine::default();
+        let annotations = engine.build_default(ASCII_TEST).unwrap();
         assert_eq!(3,annotations.v.len());
         
     }
@@ -140,7 +219,8 @@ mod tests {
         let mut store = AnnotationStore::new("test store");
         fn mka( start : usize, end : usize ) -> Box<dyn Annotation> {
             let c = format!("{}-{}",start,end).to_string();
-            Box::new(CStringAnnotation { start : start, end : end, contents : c })
+            Box::new(GenericAnnotation { start, end, type_str : "Test".to_string(),
+                                         confidence : 255, comments : c })
         }
         store.insert(mka(1,20));
         store.insert(mka(5,10));
@@ -149,6 +229,60 @@ mod tests {
         assert_eq!(1,store.query(1).len());
         assert_eq!(2,store.query(6).len());
         assert_eq!(3,store.query(9).len());
+        // the end of a span is past its last byte
+        assert_eq!(2,store.query(10).len());
+        assert_eq!(1,store.query(20).len());
+
+        // nested and overlapping spans, inserted out of order
+        store.insert(mka(6,7));
+        store.insert(mka(0,1));
+        store.insert(mka(25,40));
+        assert_eq!(1,store.query(0).len());
+        assert_eq!(3,store.query(6).len());
+        assert_eq!(2,store.query(7).len());
+        assert_eq!(2,store.query(28).len());
+        assert_eq!(1,store.query(35).len());
+        assert_eq!(0,store.query(40).len());
+        let starts : Vec<usize> = store.iter().map(|a| a.span().0).collect();
+        assert_eq!(vec![0,1,5,6,9,25], starts);
+
+        let hits : Vec<&str> = store.query_range(11,26).iter().map(|a| a.comments()).collect();
+        assert_eq!(vec!["1-20","9-30","25-40"], hits);
+        let hits : Vec<&str> = store.query_range(20,25).iter().map(|a| a.comments()).collect();
+        assert_eq!(vec!["9-30"], hits);
+        assert_eq!(6,store.query_range(0,100).len());
+
+        assert_eq!(Some((9,30)), store.next_after(6).map(|a| a.span()));
+        assert_eq!(Some((5,10)), store.prev_before(6).map(|a| a.span()));
+        assert!(store.next_after(25).is_none());
+        assert!(store.prev_before(0).is_none());
+    }
+
+    #[test]
+    fn annotation_store_matches_linear_scan() {
+        // Pseudo-random spans, some appended in order and some not.
+        let mut seed : u32 = 12345;
+        let mut rand = move |m : usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as usize % m
+        };
+        let mut spans = Vec::new();
+        let mut store = AnnotationStore::new("random");
+        for i in 0..500 {
+            let start = if i % 7 == 0 { rand(2000) } else { i * 4 };
+            let end = start + rand(if i % 50 == 0 { 500 } else { 20 });
+            spans.push((start, end));
+            store.insert(Box::new(GenericAnnotation { start, end, type_str : String::new(),
+                                                      confidence : 0, comments : String::new() }));
+        }
+        for _ in 0..500 {
+            let a = rand(2100);
+            let b = a + rand(30);
+            let expected = spans.iter().filter(|s| s.0 < b && s.1 > a).count();
+            assert_eq!(expected, store.query_range(a,b).len());
+            let expected = spans.iter().filter(|s| s.0 <= a && s.1 > a).count();
+            assert_eq!(expected, store.query(a).len());
+        }
     }
 }
 
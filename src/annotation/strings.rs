@@ -0,0 +1,371 @@
+//! Text strings: runs of printable characters, in a choice of encodings and
+//! with a choice of ways of marking where each string ends.
+
+use annotation::{Annotation, AnnotationEngine, AnnotationStore, EngineError, Parameter, Params, Progress};
+use charset::Charset;
+
+/// How characters are stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// one byte per character, through the configured charset
+    Bytes,
+    /// 16-bit little endian code units
+    Utf16Le,
+    /// 16-bit big endian code units
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn from_name(name : &str) -> Option<Encoding> {
+        match name.to_lowercase().as_str() {
+            "bytes" | "8bit" => Some(Encoding::Bytes),
+            "utf16le" | "utf-16le" => Some(Encoding::Utf16Le),
+            "utf16be" | "utf-16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+}
+
+/// How the end (or length) of a string is marked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Termination {
+    /// followed by a NUL, as in C
+    Nul,
+    /// not marked at all; any unprintable character ends the string
+    Unterminated,
+    /// the last character has bit 7 set, as in the keyword tables of
+    /// Apple and Microsoft BASIC
+    HighBit,
+    /// preceded by a length byte, as in Pascal
+    LengthPrefixed,
+}
+
+impl Termination {
+    pub fn from_name(name : &str) -> Option<Termination> {
+        match name.to_lowercase().as_str() {
+            "nul" | "c" => Some(Termination::Nul),
+            "none" | "unterminated" => Some(Termination::Unterminated),
+            "highbit" | "bit7" => Some(Termination::HighBit),
+            "pascal" | "length" => Some(Termination::LengthPrefixed),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            Termination::Nul => "NUL-terminated",
+            Termination::Unterminated => "unterminated",
+            Termination::HighBit => "high-bit-terminated",
+            Termination::LengthPrefixed => "length-prefixed",
+        }
+    }
+}
+
+/// What the string engine looks for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StringConfig {
+    /// fewest characters a string can have
+    pub min_len : usize,
+    pub encoding : Encoding,
+    pub termination : Termination,
+    /// printable characters for byte encodings
+    pub charset : Charset,
+}
+
+impl Default for StringConfig {
+    /// NUL-terminated ASCII strings longer than 4 chars.
+    fn default() -> StringConfig {
+        StringConfig {
+            min_len : 5,
+            encoding : Encoding::Bytes,
+            termination : Termination::Nul,
+            charset : Charset::ascii(),
+        }
+    }
+}
+
+impl StringConfig {
+    fn encoding_name(&self) -> &str {
+        match self.encoding {
+            Encoding::Bytes => self.charset.name(),
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+        }
+    }
+
+    /// Title of the resulting annotation store. Each configuration gets its
+    /// own, so that they show up as separate layers.
+    pub fn title(&self) -> String {
+        if *self == StringConfig::default() {
+            "C Strings".to_string()
+        } else {
+            format!("Strings ({}, {}, {}+ chars)", self.encoding_name(),
+                    self.termination.description(), self.min_len)
+        }
+    }
+}
+
+pub struct CStringAnnotation {
+    start : usize,
+    end : usize,
+    contents : String,
+    type_str : String,
+}
+
+impl Annotation for CStringAnnotation {
+    fn span(&self) -> (usize, usize) { (self.start, self.end) }
+    fn comments(&self) -> &str { self.contents.as_str() }
+    fn type_str(&self) -> &str { self.type_str.as_str() }
+    fn confidence(&self) -> u8 { 255 }
+}
+
+#[derive(Clone, Default)]
+pub struct CStringAnnotationEngine {
+    config : StringConfig,
+}
+
+static PARAMS : [Parameter; 4] = [
+    Parameter { name : "min_len", default : "5", help : "fewest characters in a string" },
+    Parameter { name : "encoding", default : "bytes", help : "bytes, utf16le or utf16be" },
+    Parameter { name : "termination", default : "nul", help : "nul, none, highbit or pascal" },
+    Parameter { name : "charset", default : "ascii",
+                help : "charset for byte encodings: ascii, petscii, ebcdic or a charset file" },
+];
+
+/// Printable Latin-1 characters; the range considered for UTF-16 text.
+fn latin1(unit : u32) -> Option<char> {
+    match unit {
+        0x09 | 0x0a | 0x0d | 0x20..=0x7e | 0xa0..=0xff => ::std::char::from_u32(unit),
+        _ => None,
+    }
+}
+
+/// Code units between progress reports.
+const PROGRESS_STRIDE : usize = 0x10000;
+
+impl CStringAnnotationEngine {
+    pub fn with_config(config : StringConfig) -> Result<CStringAnnotationEngine, EngineError> {
+        if config.min_len == 0 {
+            return Err(EngineError::BadParam("min_len".to_string(), "must be at least 1".to_string()));
+        }
+        match (config.encoding, config.termination) {
+            (Encoding::Utf16Le, Termination::HighBit) | (Encoding::Utf16Be, Termination::HighBit) |
+            (Encoding::Utf16Le, Termination::LengthPrefixed) | (Encoding::Utf16Be, Termination::LengthPrefixed) =>
+                Err(EngineError::BadParam("termination".to_string(),
+                                          format!("{} strings are only supported in byte encodings",
+                                                  config.termination.description()))),
+            _ => Ok(CStringAnnotationEngine { config }),
+        }
+    }
+
+    pub fn config(&self) -> &StringConfig { &self.config }
+
+    /// This engine's configuration with any parameters applied.
+    fn with_params(&self, params : &Params) -> Result<CStringAnnotationEngine, EngineError> {
+        params.check(&PARAMS)?;
+        let bad = |name : &str, v : &str| EngineError::BadParam(name.to_string(), format!("unknown value '{}'", v));
+        let mut config = self.config.clone();
+        config.min_len = params.get_usize("min_len", config.min_len)?;
+        if let Some(v) = params.get_str("encoding") {
+            config.encoding = Encoding::from_name(v).ok_or_else(|| bad("encoding", v))?;
+        }
+        if let Some(v) = params.get_str("termination") {
+            config.termination = Termination::from_name(v).ok_or_else(|| bad("termination", v))?;
+        }
+        if let Some(v) = params.get_str("charset") {
+            config.charset = Charset::load(v).map_err(|e| EngineError::BadParam("charset".to_string(), e))?;
+        }
+        CStringAnnotationEngine::with_config(config)
+    }
+
+    /// Find runs of characters in a sequence of code units, where `units`
+    /// yields (offset, code unit) and the encoding is `width` bytes per unit.
+    /// Progress is reported as `base` plus the offset, out of `total`.
+    fn scan_runs<I, F>(&self, units : I, width : usize, decode : F, out : &mut Vec<Box<dyn Annotation>>,
+                       progress : &mut Progress, (base, total) : (usize, usize)) -> Result<(), EngineError>
+        where I : Iterator<Item = (usize, u32)>, F : Fn(u32) -> Option<char>
+    {
+        let cfg = &self.config;
+        let type_str = format!("{} String", cfg.encoding_name());
+        let mut emit = |start : usize, end : usize, s : &str, n : usize| {
+            if n >= cfg.min_len {
+                out.push(Box::new(CStringAnnotation {
+                    start, end, contents : s.to_string(), type_str : type_str.clone() }));
+            }
+        };
+        let mut start : Option<usize> = None;
+        let mut s = String::new();
+        let mut n = 0;
+        let mut last_end = 0;
+        for (idx, unit) in units {
+            if idx % PROGRESS_STRIDE < width {
+                progress.update(base + idx, total)?;
+            }
+            let high = cfg.termination == Termination::HighBit && unit & 0x80 != 0;
+            if !high {
+                if let Some(ch) = decode(unit) {
+                    if start.is_none() { start = Some(idx); s.clear(); n = 0; }
+                    s.push(ch);
+                    n += 1;
+                    last_end = idx + width;
+                    continue;
+                }
+            }
+            // This unit ends any string in progress.
+            match cfg.termination {
+                Termination::Nul if unit == 0 => {
+                    if let Some(w) = start { emit(w, idx + width, &s, n); }
+                },
+                Termination::Unterminated => {
+                    if let Some(w) = start { emit(w, idx, &s, n); }
+                },
+                Termination::HighBit if high => {
+                    if let Some(ch) = decode(unit & 0x7f) {
+                        if start.is_none() { s.clear(); n = 0; }
+                        s.push(ch);
+                        emit(start.unwrap_or(idx), idx + width, &s, n + 1);
+                    }
+                },
+                _ => (),
+            }
+            start = None;
+        }
+        if cfg.termination == Termination::Unterminated {
+            if let Some(w) = start { emit(w, last_end, &s, n); }
+        }
+        Ok(())
+    }
+
+    fn scan_pascal(&self, raw_data : &[u8], out : &mut Vec<Box<dyn Annotation>>,
+                   progress : &mut Progress) -> Result<(), EngineError> {
+        let cfg = &self.config;
+        let type_str = format!("{} String", cfg.encoding_name());
+        let mut idx = 0;
+        let mut next_report = 0;
+        while idx < raw_data.len() {
+            if idx >= next_report {
+                progress.update(idx, raw_data.len())?;
+                next_report = idx + PROGRESS_STRIDE;
+            }
+            let n = raw_data[idx] as usize;
+            if n >= cfg.min_len && idx + 1 + n <= raw_data.len() {
+                let body = &raw_data[idx + 1..idx + 1 + n];
+                let decoded : Option<String> = body.iter().map(|&b| cfg.charset.decode(b)).collect();
+                if let Some(contents) = decoded {
+                    out.push(Box::new(CStringAnnotation {
+                        start : idx, end : idx + 1 + n, contents, type_str : type_str.clone() }));
+                    idx += 1 + n;
+                    continue;
+                }
+            }
+            idx += 1;
+        }
+        Ok(())
+    }
+}
+
+impl AnnotationEngine for CStringAnnotationEngine {
+    fn id(&self) -> &'static str { "strings" }
+
+    fn description(&self) -> &'static str { "Runs of printable text" }
+
+    fn parameters(&self) -> &'static [Parameter] { &PARAMS }
+
+    fn build_annotations(&self, raw_data : &[u8], params : &Params, progress : &mut Progress)
+                         -> Result<AnnotationStore, EngineError> {
+        let engine = self.with_params(params)?;
+        let mut annotations = AnnotationStore::new(engine.config.title().as_str());
+        let mut found = Vec::new();
+        let charset = &engine.config.charset;
+        let len = raw_data.len();
+        match (engine.config.encoding, engine.config.termination) {
+            (Encoding::Bytes, Termination::LengthPrefixed) => engine.scan_pascal(raw_data, &mut found, progress)?,
+            (Encoding::Bytes, _) => {
+                let units = raw_data.iter().enumerate().map(|(i, &b)| (i, b as u32));
+                engine.scan_runs(units, 1, |u| charset.decode(u as u8), &mut found, progress, (0, len))?;
+            },
+            (enc, _) => {
+                // Strings may start at either alignment.
+                for align in 0..2 {
+                    let units = raw_data[align.min(len)..].chunks(2)
+                        .filter(|c| c.len() == 2)
+                        .enumerate()
+                        .map(|(i, c)| {
+                            let unit = if enc == Encoding::Utf16Le {
+                                c[0] as u32 | (c[1] as u32) << 8
+                            } else {
+                                (c[0] as u32) << 8 | c[1] as u32
+                            };
+                            (align + 2 * i, unit)
+                        });
+                    engine.scan_runs(units, 2, latin1, &mut found, progress, (align * len, 2 * len))?;
+                }
+            },
+        }
+        annotations.extend(found);
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(config : StringConfig, data : &[u8]) -> Vec<((usize, usize), String)> {
+        let engine = CStringAnnotationEngine::with_config(config).unwrap();
+        engine.build_default(data).unwrap().iter().map(|a| (a.span(), a.comments().to_string())).collect()
+    }
+
+    #[test]
+    fn string_variants() {
+        let data = b"\x01hello\x00\xffworld!\x02";
+        let mut config = StringConfig::default();
+        assert_eq!(vec![((1, 7), "hello".to_string())], find(config.clone(), data));
+
+        config.termination = Termination::Unterminated;
+        assert_eq!(vec![((1, 6), "hello".to_string()), ((8, 14), "world!".to_string())],
+                   find(config.clone(), data));
+        config.min_len = 6;
+        assert_eq!(vec![((8, 14), "world!".to_string())], find(config.clone(), data));
+
+        // BASIC keyword table: last char of each keyword has bit 7 set
+        let basic = b"EN\xc4FO\xd2NEX\xd4";
+        config.min_len = 3;
+        config.termination = Termination::HighBit;
+        assert_eq!(vec![((0, 3), "END".to_string()), ((3, 6), "FOR".to_string()),
+                        ((6, 10), "NEXT".to_string())], find(config.clone(), basic));
+
+        config.termination = Termination::LengthPrefixed;
+        assert_eq!(vec![((2, 8), "HELLO".to_string())], find(config.clone(), b"\xff\x00\x05HELLO\x09"));
+
+        config.termination = Termination::Nul;
+        config.encoding = Encoding::Utf16Le;
+        let wide = b"\x00h\x00i\x00 \x00t\x00\x00\x00";
+        assert_eq!(vec![((1, 11), "hi t".to_string())], find(config.clone(), wide));
+        config.encoding = Encoding::Utf16Be;
+        assert_eq!(vec![((0, 10), "hi t".to_string())], find(config.clone(), wide));
+
+        config.encoding = Encoding::Bytes;
+        config.charset = Charset::ebcdic();
+        assert_eq!(vec![((0, 4), "ABC".to_string())], find(config.clone(), b"\xc1\xc2\xc3\x00"));
+
+        config.termination = Termination::HighBit;
+        config.encoding = Encoding::Utf16Le;
+        assert!(CStringAnnotationEngine::with_config(config).is_err());
+
+        // the same settings given as parameters
+        let engine = CStringAnnotationEngine::default();
+        let params = Params::parse("min_len=3 termination=highbit").unwrap();
+        let store = engine.build_annotations(basic, &params, &mut Progress::none()).unwrap();
+        assert_eq!(3, store.len());
+        let params = Params::parse("encoding=utf16le termination=pascal").unwrap();
+        assert!(engine.build_annotations(basic, &params, &mut Progress::none()).is_err());
+        let params = Params::parse("charset=klingon").unwrap();
+        assert!(engine.build_annotations(basic, &params, &mut Progress::none()).is_err());
+
+        // bytes that aren't valid UTF-8 just end a string
+        let params = Params::parse("min_len=3").unwrap();
+        let store = engine.build_annotations(b"\xffabc\x00", &params, &mut Progress::none()).unwrap();
+        assert_eq!("abc", store.iter().next().unwrap().comments());
+    }
+}
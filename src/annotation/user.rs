@@ -0,0 +1,85 @@
+//! Annotations made by hand in the viewer.
+
+use annotation::GenericAnnotation;
+
+/// Title of the layer holding user annotations.
+pub const USER_LAYER : &str = "User";
+
+/// What the user says a region holds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UserKind {
+    Code,
+    Data,
+    Text,
+    Graphics,
+    Padding,
+    Unknown,
+}
+
+pub static USER_KINDS : [UserKind; 6] = [
+    UserKind::Code, UserKind::Data, UserKind::Text,
+    UserKind::Graphics, UserKind::Padding, UserKind::Unknown,
+];
+
+impl UserKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            UserKind::Code => "Code",
+            UserKind::Data => "Data",
+            UserKind::Text => "Text",
+            UserKind::Graphics => "Graphics",
+            UserKind::Padding => "Padding",
+            UserKind::Unknown => "Unknown",
+        }
+    }
+
+    /// Look a kind up by its name or any prefix of it, ignoring case.
+    pub fn from_name(name : &str) -> Option<UserKind> {
+        let name = name.to_lowercase();
+        if name.is_empty() { return None; }
+        USER_KINDS.iter().cloned().find(|k| k.name().to_lowercase().starts_with(name.as_str()))
+    }
+}
+
+/// Parse a line typed by the user: the kind, then the comment. If the first
+/// word isn't a kind, the whole line is taken as the comment of an unknown region.
+pub fn parse_entry(line : &str) -> (UserKind, String) {
+    let line = line.trim();
+    let (first, rest) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim_start()),
+        None => (line, ""),
+    };
+    match UserKind::from_name(first) {
+        Some(kind) => (kind, rest.to_string()),
+        None => (UserKind::Unknown, line.to_string()),
+    }
+}
+
+/// The text that `parse_entry` would turn back into the given annotation.
+pub fn entry_text(kind : &str, comments : &str) -> String {
+    if comments.is_empty() { kind.to_lowercase() } else { format!("{} {}", kind.to_lowercase(), comments) }
+}
+
+pub fn make(kind : UserKind, span : (usize, usize), comments : String) -> GenericAnnotation {
+    GenericAnnotation {
+        start : span.0,
+        end : span.1,
+        type_str : kind.name().to_string(),
+        confidence : 255,
+        comments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_entries() {
+        assert_eq!((UserKind::Code, "reset handler".to_string()), parse_entry("code reset handler"));
+        assert_eq!((UserKind::Graphics, "".to_string()), parse_entry(" g "));
+        assert_eq!((UserKind::Padding, "ff fill".to_string()), parse_entry("PAD  ff fill"));
+        assert_eq!((UserKind::Unknown, "looks odd".to_string()), parse_entry("looks odd"));
+        assert_eq!((UserKind::Data, "x".to_string()), parse_entry(entry_text("Data", "x").as_str()));
+    }
+}
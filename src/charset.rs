@@ -0,0 +1,193 @@
+//! Character maps from byte values to printable characters, for text in
+//! encodings other than ASCII.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A mapping from each byte value to the character it prints as, if any.
+#[derive(Clone)]
+pub struct Charset {
+    name : String,
+    map : Vec<Option<char>>,
+}
+
+impl fmt::Debug for Charset {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Charset({})", self.name)
+    }
+}
+
+impl PartialEq for Charset {
+    fn eq(&self, other : &Charset) -> bool { self.name == other.name && self.map == other.map }
+}
+
+static ASCII_LOOKUP : [bool;128] = [
+    false, false, false, false,    false, false, false, false,
+    false, true,  true,  false,    false, true,  false, false,
+    false, false, false, false,    false, false, false, false,
+    false, false, false, false,    false, false, false, false,
+
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  true,
+    true,  true,  true,  true,     true,  true,  true,  false, ];
+
+// Printable EBCDIC (code page 037) punctuation; letters and digits are filled in by range.
+static EBCDIC_PUNCT : [(u8, char); 27] = [
+    (0x40, ' '), (0x4B, '.'), (0x4C, '<'), (0x4D, '('), (0x4E, '+'), (0x4F, '|'),
+    (0x50, '&'), (0x5A, '!'), (0x5B, '$'), (0x5C, '*'), (0x5D, ')'), (0x5E, ';'),
+    (0x60, '-'), (0x61, '/'), (0x6B, ','), (0x6C, '%'), (0x6D, '_'), (0x6E, '>'),
+    (0x6F, '?'), (0x7A, ':'), (0x7B, '#'), (0x7C, '@'), (0x7D, '\''), (0x7E, '='),
+    (0x7F, '"'), (0x25, '\n'), (0x05, '\t'),
+];
+
+pub static CHARSET_NAMES : [&str; 3] = ["ascii", "petscii", "ebcdic"];
+
+impl Charset {
+    fn empty(name : &str) -> Charset {
+        Charset { name : name.to_string(), map : vec![None; 256] }
+    }
+
+    fn map_range(&mut self, first : u8, last : u8, first_char : char) {
+        for (i, b) in (first..=last).enumerate() {
+            self.map[b as usize] = ::std::char::from_u32(first_char as u32 + i as u32);
+        }
+    }
+
+    /// Printable 7-bit ASCII, plus tab, CR and LF.
+    pub fn ascii() -> Charset {
+        let mut c = Charset::empty("ASCII");
+        for (b, &printable) in ASCII_LOOKUP.iter().enumerate() {
+            if printable { c.map[b] = Some(b as u8 as char); }
+        }
+        c
+    }
+
+    /// Commodore PETSCII, in the power-on upper case and graphics mode.
+    pub fn petscii() -> Charset {
+        let mut c = Charset::empty("PETSCII");
+        c.map_range(0x20, 0x5B, ' ');
+        c.map[0x5D] = Some(']');
+        c.map[0x0D] = Some('\n');
+        c
+    }
+
+    /// EBCDIC, code page 037.
+    pub fn ebcdic() -> Charset {
+        let mut c = Charset::empty("EBCDIC");
+        c.map_range(0x81, 0x89, 'a');
+        c.map_range(0x91, 0x99, 'j');
+        c.map_range(0xA2, 0xA9, 's');
+        c.map_range(0xC1, 0xC9, 'A');
+        c.map_range(0xD1, 0xD9, 'J');
+        c.map_range(0xE2, 0xE9, 'S');
+        c.map_range(0xF0, 0xF9, '0');
+        for &(b, ch) in EBCDIC_PUNCT.iter() { c.map[b as usize] = Some(ch); }
+        c
+    }
+
+    /// One of the built in charsets, by (case-insensitive) name.
+    pub fn by_name(name : &str) -> Option<Charset> {
+        match name.to_lowercase().as_str() {
+            "ascii" => Some(Charset::ascii()),
+            "petscii" => Some(Charset::petscii()),
+            "ebcdic" => Some(Charset::ebcdic()),
+            _ => None,
+        }
+    }
+
+    /// Read a user-supplied character map. Each line maps a byte, or an
+    /// inclusive range of bytes, in hex to the character for the first of
+    /// them; the rest of a range follow in order:
+    ///
+    /// ```text
+    /// # lower case letters
+    /// 01-1A a
+    /// 20 space
+    /// 0D \n
+    /// 23 hash
+    /// ```
+    /// `space`, `\n` and `\t` name the whitespace characters; `#` starts a
+    /// comment, so `hash` names it.
+    pub fn parse(name : &str, text : &str) -> Result<Charset, String> {
+        let mut c = Charset::empty(name);
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let err = |what : &str| format!("line {}: {}: {}", n + 1, what, line);
+            let mut words = line.split_whitespace();
+            let bytes = words.next().unwrap();
+            let ch = words.next().ok_or_else(|| err("missing character"))?;
+            if words.next().is_some() { return Err(err("trailing text")); }
+            let (first, last) = match bytes.find('-') {
+                Some(i) => (&bytes[..i], &bytes[i + 1..]),
+                None => (bytes, bytes),
+            };
+            let first = u8::from_str_radix(first, 16).map_err(|_| err("bad byte value"))?;
+            let last = u8::from_str_radix(last, 16).map_err(|_| err("bad byte value"))?;
+            if last < first { return Err(err("backwards range")); }
+            let ch = match ch {
+                "space" => ' ',
+                "hash" => '#',
+                "\\n" => '\n',
+                "\\t" => '\t',
+                _ if ch.chars().count() == 1 => ch.chars().next().unwrap(),
+                _ => return Err(err("expected a single character")),
+            };
+            c.map_range(first, last, ch);
+        }
+        Ok(c)
+    }
+
+    /// A built in charset by name, or else one read from the named file.
+    pub fn load(spec : &str) -> Result<Charset, String> {
+        if let Some(c) = Charset::by_name(spec) { return Ok(c); }
+        let text = fs::read_to_string(spec).map_err(|e| format!("{}: {}", spec, e))?;
+        let name = Path::new(spec).file_stem().map_or(spec.into(), |s| s.to_string_lossy());
+        Charset::parse(&name, &text).map_err(|e| format!("{}: {}", spec, e))
+    }
+
+    pub fn name(&self) -> &str { self.name.as_str() }
+
+    /// The character the byte prints as, if it is printable.
+    pub fn decode(&self, b : u8) -> Option<char> { self.map[b as usize] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charsets() {
+        let ascii = Charset::ascii();
+        assert_eq!(Some('A'), ascii.decode(0x41));
+        assert_eq!(None, ascii.decode(0));
+        assert_eq!(None, ascii.decode(0xC1));
+        let ebcdic = Charset::ebcdic();
+        assert_eq!(Some('A'), ebcdic.decode(0xC1));
+        assert_eq!(Some('z'), ebcdic.decode(0xA9));
+        assert_eq!(Some('9'), ebcdic.decode(0xF9));
+        assert_eq!(Some('A'), Charset::petscii().decode(0x41));
+
+        let custom = Charset::parse("custom", "# test\n01-1A a\n20 space\n0D \\n\n23 hash # as in ASCII\n").unwrap();
+        assert_eq!(Some('a'), custom.decode(1));
+        assert_eq!(Some('z'), custom.decode(0x1A));
+        assert_eq!(Some(' '), custom.decode(0x20));
+        assert_eq!(Some('\n'), custom.decode(0x0D));
+        assert_eq!(Some('#'), custom.decode(0x23));
+        assert_eq!(None, custom.decode(0x41));
+        assert!(Charset::parse("bad", "1G a").is_err());
+        assert!(Charset::parse("bad", "10-01 a").is_err());
+    }
+}
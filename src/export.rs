@@ -0,0 +1,190 @@
+//! Writing a range of the image out in formats other tools can take.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use hexfile;
+use layout::ByteOrder;
+use selection::Selection;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// the bytes themselves
+    Raw,
+    /// a C source array definition
+    CArray,
+    IntelHex,
+    SRecord,
+    /// hex and ASCII dump text
+    HexDump,
+}
+
+pub static FORMAT_NAMES : [&str; 5] = ["raw", "c", "ihex", "srec", "dump"];
+
+impl ExportFormat {
+    pub fn from_name(name : &str) -> Option<ExportFormat> {
+        match name.to_lowercase().as_str() {
+            "raw" | "bin" => Some(ExportFormat::Raw),
+            "c" => Some(ExportFormat::CArray),
+            "ihex" | "hex" => Some(ExportFormat::IntelHex),
+            "srec" => Some(ExportFormat::SRecord),
+            "dump" | "hexdump" => Some(ExportFormat::HexDump),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from a file name's extension.
+    pub fn from_path<P : AsRef<Path>>(path : P) -> Option<ExportFormat> {
+        let ext = path.as_ref().extension()?.to_string_lossy().to_lowercase();
+        match ext.as_str() {
+            "bin" | "rom" | "raw" => Some(ExportFormat::Raw),
+            "c" | "h" => Some(ExportFormat::CArray),
+            "hex" | "ihx" | "ihex" => Some(ExportFormat::IntelHex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(ExportFormat::SRecord),
+            "txt" | "dump" => Some(ExportFormat::HexDump),
+            _ => None,
+        }
+    }
+}
+
+/// Make a C identifier from a name, such as the name of the output file.
+fn c_identifier(name : &str) -> String {
+    let mut id : String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if id.is_empty() || id.starts_with(|c : char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+fn write_c_array<W : Write>(out : &mut W, data : &[u8], address : u64, name : &str) -> io::Result<()> {
+    let id = c_identifier(name);
+    writeln!(out, "/* {}: 0x{:x}-0x{:x}, {} bytes */", name, address,
+             address + data.len().saturating_sub(1) as u64, data.len())?;
+    writeln!(out, "const unsigned char {}[{}] = {{", id, data.len())?;
+    for chunk in data.chunks(12) {
+        let line : Vec<String> = chunk.iter().map(|b| format!("0x{:02x},", b)).collect();
+        writeln!(out, "    {}", line.join(" "))?;
+    }
+    writeln!(out, "}};")
+}
+
+/// One line of a hex dump: the address, the bytes in hex, and the bytes as
+/// ASCII. The hex column is padded to `width` bytes, with a gap after every
+/// `group` bytes.
+pub fn hexdump_line(address : u64, bytes : &[u8], width : usize, group : usize) -> String {
+    let mut line = format!("{:08x} ", address);
+    for i in 0..width {
+        if group > 0 && i % group == 0 { line.push(' '); }
+        match bytes.get(i) {
+            Some(b) => line.push_str(&format!("{:02x} ", b)),
+            None => line.push_str("   "),
+        }
+    }
+    line.push_str(" |");
+    line.extend(bytes.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }));
+    line.push('|');
+    line
+}
+
+pub fn write_hexdump<W : Write>(out : &mut W, data : &[u8], address : u64) -> io::Result<()> {
+    for (i, chunk) in data.chunks(16).enumerate() {
+        writeln!(out, "{}", hexdump_line(address + 16 * i as u64, chunk, 16, 8))?;
+    }
+    Ok(())
+}
+
+/// Dump lines for `rows` rows of `width` bytes, starting at position `start`,
+/// with the bytes in the order they are displayed. Rows past the end of the
+/// data are left out.
+pub fn dump_lines(data : &[u8], order : ByteOrder, start : usize, rows : usize,
+                  width : usize, group : usize) -> Vec<String> {
+    (0..rows).map(|r| start + r * width)
+        .take_while(|&pos| pos < data.len())
+        .map(|pos| {
+            let bytes = order.reorder(data, pos, pos + width);
+            hexdump_line(pos as u64, &bytes, width, group)
+        }).collect()
+}
+
+/// Write `data`, which was found at `address`, in the given format. `name`
+/// labels the data where the format has room for it.
+pub fn export<W : Write>(out : &mut W, data : &[u8], address : u64, format : ExportFormat,
+                         name : &str) -> io::Result<()> {
+    let address32 = || if address + data.len() as u64 > 1 << 32 {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "hex files only cover the first 4G"))
+    } else {
+        Ok(address as u32)
+    };
+    match format {
+        ExportFormat::Raw => out.write_all(data),
+        ExportFormat::CArray => write_c_array(out, data, address, name),
+        ExportFormat::IntelHex => hexfile::write_ihex(out, data, address32()?),
+        ExportFormat::SRecord => hexfile::write_srec(out, data, address32()?, name),
+        ExportFormat::HexDump => write_hexdump(out, data, address),
+    }
+}
+
+/// The selected bytes, rearranged as they are displayed if a byte order is given.
+pub fn selected_data(data : &[u8], sel : Selection, order : Option<ByteOrder>) -> Vec<u8> {
+    let end = sel.end.min(data.len().saturating_sub(1));
+    match order {
+        Some(order) => order.reorder(data, sel.start, end + 1),
+        None => data.get(sel.start..end + 1).unwrap_or(&[]).to_vec(),
+    }
+}
+
+/// Write `data`, found at `address`, to a file. Without a format, the file
+/// name's extension picks one. Returns the format used.
+pub fn export_file<P : AsRef<Path>>(path : P, data : &[u8], address : u64,
+                                    format : Option<ExportFormat>) -> io::Result<ExportFormat> {
+    let path = path.as_ref();
+    let format = match format.or_else(|| ExportFormat::from_path(path)) {
+        Some(f) => f,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "can't tell the format from the file name; use .bin, .c, .hex, .srec or .txt")),
+    };
+    let name = path.file_stem().map_or("data".into(), |s| s.to_string_lossy());
+    let mut out = BufWriter::new(File::create(path)?);
+    export(&mut out, data, address, format, &name)?;
+    out.flush()?;
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports() {
+        assert_eq!(Some(ExportFormat::SRecord), ExportFormat::from_path("out/bios.s19"));
+        assert_eq!(Some(ExportFormat::CArray), ExportFormat::from_path("font.h"));
+        assert_eq!(None, ExportFormat::from_path("noext"));
+        assert_eq!(Some(ExportFormat::IntelHex), ExportFormat::from_name("ihex"));
+
+        let mut out = Vec::new();
+        export(&mut out, b"Hi\x00", 0x10, ExportFormat::CArray, "2nd-font").unwrap();
+        assert_eq!("/* 2nd-font: 0x10-0x12, 3 bytes */\nconst unsigned char _2nd_font[3] = {\n    0x48, 0x69, 0x00,\n};\n",
+                   String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        export(&mut out, b"Hello, world!\x00\xff\x01ab", 0x100, ExportFormat::HexDump, "").unwrap();
+        assert_eq!("00000100  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 00 ff 01  |Hello, world!...|\n\
+                    00000110  61 62                                             |ab|\n",
+                   String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        assert!(export(&mut out, b"xx", 0xffff_ffff, ExportFormat::IntelHex, "").is_err());
+
+        let data = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        assert_eq!(vec![2, 3, 4], selected_data(&data, Selection::new(2, 4), None));
+        assert_eq!(vec![3, 2, 1, 0], selected_data(&data, Selection::new(0, 3), Some(ByteOrder::Little32)));
+        assert_eq!(vec![6, 7], selected_data(&data, Selection::new(6, 20), None));
+
+        let lines = dump_lines(b"ABCDEFGHIJ", ByteOrder::Little16, 0, 4, 4, 2);
+        assert_eq!(vec!["00000000  42 41  44 43  |BADC|",
+                        "00000004  46 45  48 47  |FEHG|",
+                        "00000008  4a 49         |JI|"], lines);
+    }
+}
@@ -7,6 +7,7 @@ out vec4 color;
 // The data display is organized into a number of columns of a fixed height, displayed side by side with a specified spacing between them.
 
 uniform uvec4 win;        // bounds of physical window
+uniform uint panel_width; // width, in pixels, of the dump panel at the right of the window
 
 uniform float zoom;       // zoom factor (2.0 = 2x)
 uniform vec2 ul_offset;   // offset of upper left hand corner in pixels at the current zoom level
@@ -19,14 +20,22 @@ uniform uint datalen;     // total length of data, in bytes
 uniform uint dataoff;     // offset before start of data to display, in bytes
 uniform uint bpp;         // bits per pixel (1 for bitmap, 8 for bytemap, etc)
 
-// Disabling endian swap for now
-// uniform bool swap_endian; // swap byte-endianness when true
+uniform uint byte_perm[8]; // stored byte shown at each position of a group of perm_len bytes
+uniform uint perm_len;     // size of the byte order's groups; 1 shows bytes as stored
 
-uniform uvec2 selection;  // start and end of selection, in bytes
+uniform uvec2 selection;  // first and last byte of the selection
+uniform uint selection_on; // 1 if there is a selection
+uniform uint highlight;   // byte picked out by "go to"
+uniform uint highlight_on; // 1 if there is such a byte
 uniform uint texwidth;    // width of data texture
 
 uniform usampler2D romtex;  // data texture
-uniform usampler2D annotex; // annotation texture
+uniform usampler2D annotex; // annotation texture: 1 + index of the topmost visible layer, or 0
+uniform vec3 layer_colors[16]; // colour of each annotation layer
+
+uniform uint view_mode;      // 0 = raw values, 1 = entropy heat map
+uniform uint entropy_block;  // size, in bytes, of each block in the entropy texture
+uniform usampler2D entropytex; // entropy of each block, in 32nds of a bit per byte
 
 
 
@@ -34,6 +43,11 @@ void main() {
     // Convert from texture coordinates to screen coordinates.
     vec2 fc = vec2( v_tex_coords[0] * float(win[2]),
                     (1.0 - v_tex_coords[1]) * float(win[3]));
+    // The dump panel is drawn over a plain background.
+    if (fc[0] >= float(win[2]) - float(panel_width)) {
+        color = vec4(0.1,0.1,0.1,1.0);
+        return;
+    }
     // Scale the coordinates by the zoom factor and adjust for the panning location.
     fc = (fc + ul_offset) / zoom;
     // Handle points above or to the left of the bitmap display.
@@ -64,11 +78,8 @@ void main() {
     uint tex_off = (elidx / el_per_b) + dataoff; // byte into array
     uint tex_rem = elidx % el_per_b; // element into array; bits into array
 
-    // Disabling endianness swap until we find a more reasonable way of expressing it.
-    //if (swap_endian == true && bitstride > 8u) {
-    //    uint bytes = bitstride / 8u;
-    //    tex_off = ((tex_off / bytes) * bytes) + (bytes - (1u+(tex_off % bytes)));
-    //}
+    // Rearrange the bytes of each group according to the byte order.
+    tex_off = (tex_off - tex_off % perm_len) + byte_perm[tex_off % perm_len];
 
     // Handle points past the end of the data.
     if (tex_off >= datalen) {
@@ -89,15 +100,30 @@ void main() {
     uint tex_val = (tex_byte >> tex_shift) & tex_mask;
     float rv = float(tex_val) / float(tex_mask);
 
+    vec4 c = vec4(rv,rv,rv, 1.0);
+    if (view_mode == 1u) {
+        // Heat map: blue for low entropy through green to red for high.
+        uint blk = tex_off / entropy_block;
+        uint e = texelFetch(entropytex, ivec2(int(blk % texwidth), int(blk / texwidth)), 0).r;
+        float t = clamp(float(e) / 256.0, 0.0, 1.0);
+        if (t < 0.5) {
+            c.rgb = mix(vec3(0.0,0.0,1.0), vec3(0.0,1.0,0.0), t * 2.0);
+        } else {
+            c.rgb = mix(vec3(0.0,1.0,0.0), vec3(1.0,0.0,0.0), t * 2.0 - 1.0);
+        }
+    }
+
     // get annotation
     uint anno = texelFetch(annotex, ivec2(int(tex_off_x),int(tex_off_y)),0).r;
-    vec4 c = vec4(rv,rv+float(anno),rv, 1.0);
     if (anno != 0u) {
-        c.r = 0.0; 
+        c.rgb = mix(c.rgb, layer_colors[anno-1u], 0.6);
     }
-    if (selection[0] != selection[1] && elidx >= selection[0] && elidx <= selection[1]+7u) {
+    if (selection_on == 1u && tex_off >= selection[0] && tex_off <= selection[1]) {
         c.b = 0.0; c.g = 0.0;
     }
+    if (highlight_on == 1u && tex_off == highlight) {
+        c.rgb = mix(c.rgb, vec3(1.0,1.0,0.0), 0.75);
+    }
     color = c;
     //color = vec4(0.0,0.0,0.4,1.0);
 }
@@ -0,0 +1,354 @@
+//! Intel HEX and Motorola S-record files, the usual way of handing an image
+//! to an EPROM programmer, and TI-TXT, as used for the MSP430.
+
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Data bytes per record.
+const RECORD_LEN : usize = 16;
+
+/// Largest image a hex file may describe, from its lowest address to its highest
+pub const MAX_IMAGE : u64 = 256 << 20;
+
+fn write_ihex_record<W : Write>(out : &mut W, kind : u8, address : u16, data : &[u8]) -> io::Result<()> {
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add((address >> 8) as u8).wrapping_add(address as u8).wrapping_add(kind);
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), address, kind)?;
+    for &b in data {
+        write!(out, "{:02X}", b)?;
+        sum = sum.wrapping_add(b);
+    }
+    writeln!(out, "{:02X}", sum.wrapping_neg())
+}
+
+/// Write `data` as Intel HEX, loaded at `address`. Extended linear address
+/// records are added as needed for addresses above 64k.
+pub fn write_ihex<W : Write>(out : &mut W, data : &[u8], address : u32) -> io::Result<()> {
+    let mut upper = 0u16;
+    let mut offset = 0;
+    while offset < data.len() {
+        let addr = address as usize + offset;
+        if addr > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "data runs past 4G"));
+        }
+        if (addr >> 16) as u16 != upper {
+            upper = (addr >> 16) as u16;
+            write_ihex_record(out, 4, 0, &[(upper >> 8) as u8, upper as u8])?;
+        }
+        // Records don't cross a 64k boundary.
+        let room = 0x10000 - (addr & 0xffff);
+        let len = RECORD_LEN.min(data.len() - offset).min(room);
+        write_ihex_record(out, 0, addr as u16, &data[offset..offset + len])?;
+        offset += len;
+    }
+    write_ihex_record(out, 1, 0, &[])
+}
+
+fn write_srec_record<W : Write>(out : &mut W, kind : u8, addr_len : usize, address : u32, data : &[u8]) -> io::Result<()> {
+    let count = addr_len + data.len() + 1;
+    let mut sum = count as u8;
+    write!(out, "S{}{:02X}", kind, count)?;
+    for i in (0..addr_len).rev() {
+        let b = (address >> (8 * i)) as u8;
+        write!(out, "{:02X}", b)?;
+        sum = sum.wrapping_add(b);
+    }
+    for &b in data {
+        write!(out, "{:02X}", b)?;
+        sum = sum.wrapping_add(b);
+    }
+    writeln!(out, "{:02X}", !sum)
+}
+
+/// Write `data` as Motorola S-records loaded at `address`, with `header` in
+/// the S0 record. The narrowest address size that fits is used (S1, S2 or S3).
+pub fn write_srec<W : Write>(out : &mut W, data : &[u8], address : u32, header : &str) -> io::Result<()> {
+    let last = address as u64 + data.len().saturating_sub(1) as u64;
+    if last > u32::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "data runs past 4G"));
+    }
+    let (kind, addr_len) = if last <= 0xffff { (1, 2) } else if last <= 0xff_ffff { (2, 3) } else { (3, 4) };
+    let header : Vec<u8> = header.bytes().take(60).collect();
+    write_srec_record(out, 0, 2, 0, &header)?;
+    let mut records = 0u32;
+    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+        write_srec_record(out, kind, addr_len, address + (i * RECORD_LEN) as u32, chunk)?;
+        records += 1;
+    }
+    if records <= 0xffff {
+        write_srec_record(out, 5, 2, records, &[])?;
+    } else {
+        write_srec_record(out, 6, 3, records, &[])?;
+    }
+    // S9, S8 or S7: the termination record matching the data records
+    write_srec_record(out, 10 - kind, addr_len, address, &[])
+}
+
+/// Text formats that describe an image by address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HexFormat {
+    IntelHex,
+    SRecord,
+    TiTxt,
+}
+
+impl HexFormat {
+    pub fn from_name(name : &str) -> Option<HexFormat> {
+        match name {
+            "ihex" | "hex" => Some(HexFormat::IntelHex),
+            "srec" | "s19" => Some(HexFormat::SRecord),
+            "titxt" | "ti-txt" => Some(HexFormat::TiTxt),
+            _ => None,
+        }
+    }
+
+    /// Whether a file's extension suggests it might hold one of the formats.
+    pub fn likely_path<P : AsRef<Path>>(path : P) -> bool {
+        let ext = path.as_ref().extension().map(|e| e.to_string_lossy().to_lowercase());
+        matches!(ext.as_deref(), Some("hex") | Some("ihx") | Some("ihex") | Some("mcs") | Some("h86") |
+                 Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") | Some("sx") |
+                 Some("txt"))
+    }
+
+    /// Guess the format from the first line of the file, if it looks like one.
+    pub fn detect(data : &[u8]) -> Option<HexFormat> {
+        let text = ::std::str::from_utf8(data).ok()?;
+        let first = text.lines().map(|l| l.trim()).find(|l| !l.is_empty())?;
+        let hex = |s : &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_hexdigit());
+        if first.starts_with(':') && hex(&first[1..]) {
+            Some(HexFormat::IntelHex)
+        } else if first.len() > 2 && first.starts_with('S') && hex(&first[1..]) {
+            Some(HexFormat::SRecord)
+        } else if first.starts_with('@') && hex(first[1..].trim()) {
+            Some(HexFormat::TiTxt)
+        } else {
+            None
+        }
+    }
+}
+
+/// An image built from a hex file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HexImage {
+    pub data : Vec<u8>,
+    /// address of the first byte
+    pub base : u64,
+    /// offset and length of each run of bytes the file gave, in order; the
+    /// gaps between were filled in
+    pub blocks : Vec<(usize, usize)>,
+}
+
+/// Lay out runs of bytes by address, filling the gaps between them.
+fn assemble(chunks : Vec<(u64, Vec<u8>)>, fill : u8) -> Result<HexImage, String> {
+    let chunks : Vec<(u64, Vec<u8>)> = chunks.into_iter().filter(|c| !c.1.is_empty()).collect();
+    let base = chunks.iter().map(|c| c.0).min().unwrap_or(0);
+    let mut end = 0;
+    for c in &chunks {
+        let chunk_end = c.0.checked_add(c.1.len() as u64)
+            .ok_or_else(|| format!("address out of range: the data at 0x{:x} runs off the end of memory", c.0))?;
+        end = end.max(chunk_end);
+    }
+    if end - base > MAX_IMAGE {
+        return Err(format!("the data runs from 0x{:x} to 0x{:x}, which is too far apart to fill in", base, end));
+    }
+    let mut data = vec![fill; (end - base) as usize];
+    let mut given = vec![false; data.len()];
+    for (address, bytes) in chunks {
+        let start = (address - base) as usize;
+        data[start..start + bytes.len()].copy_from_slice(&bytes);
+        for g in &mut given[start..start + bytes.len()] { *g = true; }
+    }
+    let mut blocks : Vec<(usize, usize)> = Vec::new();
+    for (i, &g) in given.iter().enumerate() {
+        if !g { continue; }
+        match blocks.last_mut() {
+            Some(b) if b.0 + b.1 == i => b.1 += 1,
+            _ => blocks.push((i, 1)),
+        }
+    }
+    Ok(HexImage { data, base, blocks })
+}
+
+/// The bytes spelled out by a string of hex digit pairs.
+fn hex_bytes(text : &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(format!("\"{}\" is not a whole number of hex bytes", text));
+    }
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| format!("\"{}\" is not hex", &text[i..i + 2])))
+        .collect()
+}
+
+/// Build the image described by Intel HEX records.
+pub fn read_ihex(text : &str, fill : u8) -> Result<HexImage, String> {
+    let mut chunks = Vec::new();
+    let mut upper = 0u64;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let fail = |e : String| format!("line {}: {}", n + 1, e);
+        if !line.starts_with(':') {
+            return Err(fail("records start with ':'".to_string()));
+        }
+        let rec = hex_bytes(&line[1..]).map_err(fail)?;
+        if rec.len() < 5 || rec.len() != rec[0] as usize + 5 {
+            return Err(fail("the record's length doesn't match its byte count".to_string()));
+        }
+        if rec.iter().fold(0u8, |s, &b| s.wrapping_add(b)) != 0 {
+            return Err(fail("bad checksum".to_string()));
+        }
+        let address = (rec[1] as u64) << 8 | rec[2] as u64;
+        let data = &rec[4..rec.len() - 1];
+        match rec[3] {
+            0 => chunks.push((upper + address, data.to_vec())),
+            1 => break,
+            2 if data.len() == 2 => upper = ((data[0] as u64) << 8 | data[1] as u64) << 4,
+            4 if data.len() == 2 => upper = ((data[0] as u64) << 8 | data[1] as u64) << 16,
+            3 | 5 => {}, // start address
+            kind => return Err(fail(format!("unexpected record type {:02X}", kind))),
+        }
+    }
+    assemble(chunks, fill)
+}
+
+/// Build the image described by Motorola S-records.
+pub fn read_srec(text : &str, fill : u8) -> Result<HexImage, String> {
+    let mut chunks = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let fail = |e : String| format!("line {}: {}", n + 1, e);
+        let kind = match line.as_bytes() {
+            [b'S', k, ..] if k.is_ascii_digit() => k - b'0',
+            _ => return Err(fail("records start with S and a digit".to_string())),
+        };
+        let rec = hex_bytes(&line[2..]).map_err(fail)?;
+        if rec.is_empty() || rec.len() != rec[0] as usize + 1 {
+            return Err(fail("the record's length doesn't match its byte count".to_string()));
+        }
+        if rec.iter().fold(0u8, |s, &b| s.wrapping_add(b)) != 0xff {
+            return Err(fail("bad checksum".to_string()));
+        }
+        let addr_len = match kind {
+            1 | 9 => 2,
+            2 | 8 => 3,
+            3 | 7 => 4,
+            0 | 5 | 6 => continue, // header and record counts
+            _ => return Err(fail(format!("unexpected record type S{}", kind))),
+        };
+        if kind >= 7 { break; }
+        if rec.len() < addr_len + 2 {
+            return Err(fail("the record is too short for its address".to_string()));
+        }
+        let address = rec[1..1 + addr_len].iter().fold(0u64, |a, &b| a << 8 | b as u64);
+        chunks.push((address, rec[1 + addr_len..rec.len() - 1].to_vec()));
+    }
+    assemble(chunks, fill)
+}
+
+/// Build the image described by a TI-TXT file: `@address` lines followed by
+/// lines of hex bytes, ending with `q`.
+pub fn read_titxt(text : &str, fill : u8) -> Result<HexImage, String> {
+    let mut chunks : Vec<(u64, Vec<u8>)> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let fail = |e : String| format!("line {}: {}", n + 1, e);
+        if line.is_empty() { continue; }
+        if line.eq_ignore_ascii_case("q") { break; }
+        if let Some(address) = line.strip_prefix('@') {
+            let address = u64::from_str_radix(address.trim(), 16)
+                .map_err(|_| fail(format!("\"{}\" is not a hex address", address)))?;
+            chunks.push((address, Vec::new()));
+            continue;
+        }
+        let chunk = match chunks.last_mut() {
+            Some(c) => c,
+            None => return Err(fail("data before the first @address".to_string())),
+        };
+        for byte in line.split_whitespace() {
+            match hex_bytes(byte) {
+                Ok(ref b) if b.len() == 1 => chunk.1.push(b[0]),
+                _ => return Err(fail(format!("\"{}\" is not a hex byte", byte))),
+            }
+        }
+    }
+    assemble(chunks, fill)
+}
+
+/// Build the image described by a file in the given format.
+pub fn read(format : HexFormat, text : &str, fill : u8) -> Result<HexImage, String> {
+    match format {
+        HexFormat::IntelHex => read_ihex(text, fill),
+        HexFormat::SRecord => read_srec(text, fill),
+        HexFormat::TiTxt => read_titxt(text, fill),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_writers() {
+        let mut out = Vec::new();
+        write_ihex(&mut out, b"\x01\x02\x03", 0x0100).unwrap();
+        assert_eq!(":03010000010203F6\n:00000001FF\n", String::from_utf8(out).unwrap());
+
+        // crossing into the second 64k
+        let mut out = Vec::new();
+        write_ihex(&mut out, &[0xaa; 4], 0xfffe).unwrap();
+        assert_eq!(":02FFFE00AAAAAD\n:020000040001F9\n:02000000AAAAAA\n:00000001FF\n",
+                   String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        write_srec(&mut out, b"\x01\x02\x03", 0x0100, "HI").unwrap();
+        assert_eq!("S0050000484969\nS1060100010203F2\nS5030001FB\nS9030100FB\n", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        write_srec(&mut out, b"\x00", 0x12345, "").unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("S2050123450091\n"));
+        assert!(text.ends_with("S80401234592\n"));
+    }
+
+    #[test]
+    fn hex_readers() {
+        // What we write, we can read back.
+        let data : Vec<u8> = (0..40).collect();
+        let mut out = Vec::new();
+        write_ihex(&mut out, &data, 0xfff0).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(Some(HexFormat::IntelHex), HexFormat::detect(text.as_bytes()));
+        let image = read_ihex(&text, 0xff).unwrap();
+        assert_eq!((0xfff0, &data, vec![(0, 40)]), (image.base, &image.data, image.blocks));
+
+        let mut out = Vec::new();
+        write_srec(&mut out, &data, 0x12340, "test").unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(Some(HexFormat::SRecord), HexFormat::detect(text.as_bytes()));
+        let image = read_srec(&text, 0xff).unwrap();
+        assert_eq!((0x12340, &data), (image.base, &image.data));
+
+        // Gaps are filled.
+        let image = read_ihex(":02010000AABB98\n:0101040011E9\n:00000001FF\n", 0xee).unwrap();
+        assert_eq!(0x100, image.base);
+        assert_eq!(vec![0xaa, 0xbb, 0xee, 0xee, 0x11], image.data);
+        assert_eq!(vec![(0, 2), (4, 1)], image.blocks);
+        assert!(read_ihex(":02010000AABB99\n", 0).is_err());
+
+        let text = "@F000\n31 40 00 03\nB2 40\n@FFFE\n00 F0\nq\n";
+        assert_eq!(Some(HexFormat::TiTxt), HexFormat::detect(text.as_bytes()));
+        let image = read_titxt(text, 0xff).unwrap();
+        assert_eq!(0xf000, image.base);
+        assert_eq!(0x1000, image.data.len());
+        assert_eq!(&[0x31, 0x40, 0x00, 0x03, 0xb2, 0x40, 0xff], &image.data[..7]);
+        assert_eq!(&[0x00, 0xf0], &image.data[0xffe..]);
+        assert!(read_titxt("31 40\n", 0).is_err());
+        assert!(read_titxt("@FFFFFFFFFFFFFFFF\n00 01\n", 0).unwrap_err().starts_with("address out of range"));
+
+        assert_eq!(None, HexFormat::detect(b"\x7fELF"));
+        assert_eq!(None, HexFormat::detect(b"Some text"));
+        assert!(read_ihex(":020000040000FA\n:0100000000FF\n:020000042000DA\n:0100000000FF\n", 0).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use memmap::{Mmap, Protection};
+
+use annotation::{AnnotationStore, GenericAnnotation};
+use hexfile::{self, HexFormat, HexImage};
+use parse;
+
+/// Title of the layer showing which file each part of the image came from
+pub const FILES_LAYER : &str = "Files";
+
+/// How several files are put together into one image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interleave {
+    /// one after another, as for the banks of a larger chip
+    Concat,
+    /// taking this many bytes from each file in turn, as for the even and
+    /// odd chips of a 16-bit bus
+    Units(usize),
+}
+
+impl Interleave {
+    /// `concat`, `byte`, `word`, `long`, or a number of bytes per unit.
+    pub fn from_name(name : &str) -> Result<Interleave, String> {
+        match name {
+            "concat" => Ok(Interleave::Concat),
+            "byte" => Ok(Interleave::Units(1)),
+            "word" => Ok(Interleave::Units(2)),
+            "long" => Ok(Interleave::Units(4)),
+            _ => match parse::parse_usize(name) {
+                Ok(n) if n > 0 => Ok(Interleave::Units(n)),
+                _ => Err(format!("unknown interleave {}; use concat, byte, word, long or a number of bytes", name)),
+            },
+        }
+    }
+}
+
+/// One of the files an image was built from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    pub path : PathBuf,
+    pub len : usize,
+}
+
+enum Storage {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+/// A ROM image opened for analysis: a single file, or several merged.
+pub struct RomImage {
+    storage : Storage,
+    sources : Vec<Source>,
+    interleave : Interleave,
+    /// address of the first byte, when a hex file gave one
+    base : Option<u64>,
+    /// offset and length of the runs of bytes a hex file gave
+    blocks : Vec<(usize, usize)>,
+}
+
+/// Read a file as Intel HEX, S-records or TI-TXT, if its name and first line
+/// suggest it is one.
+fn load_hex(path : &Path, fill : u8) -> io::Result<Option<HexImage>> {
+    if !HexFormat::likely_path(path) {
+        return Ok(None);
+    }
+    let data = fs::read(path)?;
+    let format = match HexFormat::detect(&data) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let text = String::from_utf8_lossy(&data);
+    hexfile::read(format, &text, fill).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl RomImage {
+    /// Map the file at `path` read-only.
+    pub fn open<P : AsRef<Path>>(path : P) -> io::Result<RomImage> {
+        let map = Mmap::open_path(path.as_ref(), Protection::Read)?;
+        let sources = vec![Source { path : path.as_ref().to_path_buf(), len : map.len() }];
+        Ok(RomImage { storage : Storage::Mapped(map), sources, interleave : Interleave::Concat,
+                      base : None, blocks : Vec::new() })
+    }
+
+    /// The image built from a hex file read from `path`.
+    pub fn from_hex<P : AsRef<Path>>(path : P, hex : HexImage) -> RomImage {
+        let sources = vec![Source { path : path.as_ref().to_path_buf(), len : hex.data.len() }];
+        RomImage { storage : Storage::Owned(hex.data), sources, interleave : Interleave::Concat,
+                   base : Some(hex.base), blocks : hex.blocks }
+    }
+
+    /// Open one file, or read several and merge them into one image. Hex
+    /// files are decoded, with the gaps between their records set to `fill`;
+    /// anything else is taken as a raw binary.
+    pub fn open_many<P : AsRef<Path>>(paths : &[P], interleave : Interleave, fill : u8) -> io::Result<RomImage> {
+        if paths.len() == 1 {
+            return match load_hex(paths[0].as_ref(), fill)? {
+                Some(hex) => Ok(RomImage::from_hex(&paths[0], hex)),
+                None => RomImage::open(&paths[0]),
+            };
+        }
+        let mut parts = Vec::new();
+        for p in paths {
+            let data = match load_hex(p.as_ref(), fill)? {
+                Some(hex) => hex.data,
+                None => fs::read(p)?,
+            };
+            parts.push((p.as_ref().to_path_buf(), data));
+        }
+        RomImage::combine(parts, interleave).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Merge the contents of several files. Interleaved files must all be the
+    /// same size, and a whole number of units long.
+    pub fn combine(parts : Vec<(PathBuf, Vec<u8>)>, interleave : Interleave) -> Result<RomImage, String> {
+        let sources : Vec<Source> = parts.iter().map(|(path, d)| Source { path : path.clone(), len : d.len() }).collect();
+        let data = match interleave {
+            Interleave::Concat => parts.into_iter().flat_map(|(_, d)| d).collect(),
+            Interleave::Units(unit) => {
+                let len = sources.first().map_or(0, |s| s.len);
+                if let Some(s) = sources.iter().find(|s| s.len != len || s.len % unit != 0) {
+                    return Err(format!("{} is {} bytes; interleaved files must all be the same size, \
+                                        and a multiple of {} bytes", s.path.display(), s.len, unit));
+                }
+                let mut data = Vec::with_capacity(len * parts.len());
+                for i in (0..len).step_by(unit) {
+                    for (_, d) in &parts {
+                        data.extend_from_slice(&d[i..i + unit]);
+                    }
+                }
+                data
+            },
+        };
+        Ok(RomImage { storage : Storage::Owned(data), sources, interleave, base : None, blocks : Vec::new() })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self.storage {
+            // The mapping is read-only and we never hand out mutable access to it.
+            Storage::Mapped(ref map) => unsafe { map.as_slice() },
+            Storage::Owned(ref v) => v.as_slice(),
+        }
+    }
+
+    pub fn len(&self) -> usize { self.data().len() }
+
+    pub fn is_empty(&self) -> bool { self.data().is_empty() }
+
+    pub fn sources(&self) -> &[Source] { &self.sources }
+
+    /// The address the image starts at, if the file it came from said.
+    pub fn base(&self) -> Option<u64> { self.base }
+
+    /// Whether the image was pieced together, from several files or from a
+    /// hex file with gaps, so that `files_layer` has something to show.
+    pub fn is_pieced(&self) -> bool { self.sources.len() > 1 || self.blocks.len() > 1 }
+
+    /// Where the project for the image is kept: beside the first file, named
+    /// after all of them.
+    pub fn path(&self) -> PathBuf {
+        let mut name = self.sources[0].path.clone().into_os_string();
+        for s in &self.sources[1..] {
+            name.push("+");
+            name.push(s.path.file_name().unwrap_or(s.path.as_os_str()));
+        }
+        PathBuf::from(name)
+    }
+
+    /// The file, and offset in it, that a byte of the image came from.
+    pub fn source_of(&self, pos : usize) -> Option<(&Source, usize)> {
+        match self.interleave {
+            Interleave::Concat => {
+                let mut start = 0;
+                for s in &self.sources {
+                    if pos < start + s.len { return Some((s, pos - start)); }
+                    start += s.len;
+                }
+                None
+            },
+            Interleave::Units(unit) => {
+                if pos >= self.len() { return None; }
+                let n = self.sources.len();
+                let u = pos / unit;
+                Some((&self.sources[u % n], (u / n) * unit + pos % unit))
+            },
+        }
+    }
+
+    /// Annotations showing where each file went: a span per file when they
+    /// were concatenated, or one over the whole image when interleaved. For
+    /// a hex file, there is a span per run of bytes it gave.
+    pub fn files_layer(&self) -> AnnotationStore {
+        let mut store = AnnotationStore::new(FILES_LAYER);
+        let name = |s : &Source| s.path.file_name().unwrap_or(s.path.as_os_str()).to_string_lossy().into_owned();
+        let mut add = |start : usize, len : usize, comments : String| if len > 0 {
+            store.insert(Box::new(GenericAnnotation { start, end : start + len, type_str : "File".to_string(),
+                                                      confidence : 255, comments }));
+        };
+        match self.interleave {
+            Interleave::Concat if !self.blocks.is_empty() => {
+                let base = self.base.unwrap_or(0);
+                for &(start, len) in &self.blocks {
+                    add(start, len, format!("{} at 0x{:x} ({} bytes)", name(&self.sources[0]), base + start as u64, len));
+                }
+            },
+            Interleave::Concat => {
+                let mut start = 0;
+                for s in &self.sources {
+                    add(start, s.len, format!("{} ({} bytes)", name(s), s.len));
+                    start += s.len;
+                }
+            },
+            Interleave::Units(unit) => {
+                let names : Vec<String> = self.sources.iter().map(name).collect();
+                add(0, self.len(), format!("{} interleaved by {} byte{}", names.join(", "), unit,
+                                           if unit == 1 { "" } else { "s" }));
+            },
+        }
+        store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_images() {
+        let parts = || vec![(PathBuf::from("roms/even.bin"), vec![0u8, 2, 4, 6]),
+                            (PathBuf::from("roms/odd.bin"), vec![1u8, 3, 5, 7])];
+        let bytes = RomImage::combine(parts(), Interleave::Units(1)).unwrap();
+        assert_eq!(&[0, 1, 2, 3, 4, 5, 6, 7], bytes.data());
+        let (src, off) = bytes.source_of(5).unwrap();
+        assert_eq!((Path::new("roms/odd.bin"), 2), (src.path.as_path(), off));
+        assert_eq!(PathBuf::from("roms/even.bin+odd.bin"), bytes.path());
+
+        let words = RomImage::combine(parts(), Interleave::Units(2)).unwrap();
+        assert_eq!(&[0, 2, 1, 3, 4, 6, 5, 7], words.data());
+        let (src, off) = words.source_of(7).unwrap();
+        assert_eq!((Path::new("roms/odd.bin"), 3), (src.path.as_path(), off));
+        assert!(words.source_of(8).is_none());
+
+        let concat = RomImage::combine(parts(), Interleave::Concat).unwrap();
+        assert_eq!(&[0, 2, 4, 6, 1, 3, 5, 7], concat.data());
+        assert_eq!(1, concat.source_of(5).unwrap().1);
+        let files = concat.files_layer();
+        assert_eq!(Some((4, 8)), files.query(6).first().map(|a| a.span()));
+        assert_eq!("odd.bin (4 bytes)", files.query(6)[0].comments());
+        assert_eq!(1, files.query(4).len());
+        assert!(files.query(8).is_empty());
+
+        assert!(RomImage::combine(parts(), Interleave::Units(3)).is_err());
+        let mut uneven = parts();
+        uneven[1].1.pop();
+        assert!(RomImage::combine(uneven, Interleave::Units(1)).is_err());
+        assert_eq!(Ok(Interleave::Units(2)), Interleave::from_name("word"));
+        assert_eq!(Ok(Interleave::Units(8)), Interleave::from_name("8"));
+        assert!(Interleave::from_name("0").is_err());
+
+        let hex = hexfile::read_ihex(":02010000AABB98\n:0101040011E9\n:00000001FF\n", 0xff).unwrap();
+        let image = RomImage::from_hex("fw.hex", hex);
+        assert_eq!(Some(0x100), image.base());
+        assert_eq!(&[0xaa, 0xbb, 0xff, 0xff, 0x11], image.data());
+        let files = image.files_layer();
+        assert!(files.query(2).is_empty());
+        assert_eq!("fw.hex at 0x104 (1 bytes)", files.query(4)[0].comments());
+    }
+}
@@ -0,0 +1,115 @@
+//! Decoding the bytes at a position as the common numeric and text types,
+//! for the data inspector.
+
+use charset::Charset;
+
+/// One interpretation of the bytes: what it is, and the value it gives.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name : String,
+    pub value : String,
+}
+
+impl Field {
+    fn new(name : &str, value : String) -> Field {
+        Field { name : name.to_string(), value }
+    }
+}
+
+/// The `n` bytes at `pos` as an unsigned number, big or little endian.
+fn read_uint(data : &[u8], pos : usize, n : usize, little : bool) -> Option<u64> {
+    let bytes = data.get(pos..pos.checked_add(n)?)?;
+    let fold = |acc : u64, &b : &u8| (acc << 8) | b as u64;
+    Some(if little { bytes.iter().rev().fold(0, fold) } else { bytes.iter().fold(0, fold) })
+}
+
+/// Sign extend the low `bits` bits of `v`.
+fn signed(v : u64, bits : u32) -> i64 {
+    let shift = 64 - bits;
+    ((v << shift) as i64) >> shift
+}
+
+/// Plain notation for everyday magnitudes, scientific for the rest.
+fn format_float(f : f64) -> String {
+    let a = f.abs();
+    if a == 0.0 || !a.is_finite() || (1e-4..1e9).contains(&a) { format!("{}", f) } else { format!("{:e}", f) }
+}
+
+/// Packed BCD digits of `v`, which is `n` bytes long, or "-" if a nibble isn't a digit.
+fn format_bcd(v : u64, n : usize) -> String {
+    let digits = format!("{:01$x}", v, n * 2);
+    if digits.bytes().all(|c| c.is_ascii_digit()) { digits } else { "-".to_string() }
+}
+
+/// Decode the bytes starting at `pos`. Types that would run off the end of
+/// the data are left out.
+pub fn inspect(data : &[u8], pos : usize, charset : &Charset) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let b = match data.get(pos) {
+        Some(&b) => b,
+        None => return fields,
+    };
+    fields.push(Field::new("u8", format!("0x{:02x} {}", b, b)));
+    fields.push(Field::new("i8", format!("{}", b as i8)));
+    fields.push(Field::new("bin", format!("{:08b}", b)));
+    for &n in &[2, 4, 8] {
+        let bits = 8 * n as u32;
+        for &(little, end) in &[(false, "be"), (true, "le")] {
+            if let Some(v) = read_uint(data, pos, n, little) {
+                fields.push(Field::new(&format!("u{} {}", bits, end), format!("0x{:01$x} {2}", v, n * 2, v)));
+                fields.push(Field::new(&format!("i{} {}", bits, end), format!("{}", signed(v, bits))));
+            }
+        }
+    }
+    for &(little, end) in &[(false, "be"), (true, "le")] {
+        if let Some(v) = read_uint(data, pos, 4, little) {
+            fields.push(Field::new(&format!("f32 {}", end), format_float(f32::from_bits(v as u32) as f64)));
+        }
+        if let Some(v) = read_uint(data, pos, 8, little) {
+            fields.push(Field::new(&format!("f64 {}", end), format_float(f64::from_bits(v))));
+        }
+    }
+    let bcd : Vec<String> = [1, 2, 4].iter()
+        .filter_map(|&n| read_uint(data, pos, n, false).map(|v| format_bcd(v, n)))
+        .collect();
+    fields.push(Field::new("bcd", bcd.join(" ")));
+    // 6502 and Z80 store pointers little endian; 6809 and 68000, big endian
+    if let Some(v) = read_uint(data, pos, 2, true) {
+        fields.push(Field::new("ptr le", format!("${:04X}", v)));
+    }
+    if let Some(v) = read_uint(data, pos, 2, false) {
+        fields.push(Field::new("ptr be", format!("${:04X}", v)));
+    }
+    let c = match charset.decode(b) {
+        Some(c) => format!("{:?}", c),
+        None => "-".to_string(),
+    };
+    fields.push(Field::new("char", format!("{} ({})", c, charset.name())));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inspector_fields() {
+        let data = [0x12u8, 0x34, 0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0xc1];
+        let value = |pos : usize, name : &str| inspect(&data, pos, &Charset::ebcdic()).into_iter()
+            .find(|f| f.name == name).map(|f| f.value);
+        assert_eq!(Some("0x12 18".to_string()), value(0, "u8"));
+        assert_eq!(Some("0x1234 4660".to_string()), value(0, "u16 be"));
+        assert_eq!(Some("0x3412 13330".to_string()), value(0, "u16 le"));
+        assert_eq!(Some("0x12340000 305397760".to_string()), value(0, "u32 be"));
+        assert_eq!(Some("0x00003f8000003412 69818988377106".to_string()), value(0, "u64 le"));
+        assert_eq!(Some("12 1234 12340000".to_string()), value(0, "bcd"));
+        assert_eq!(Some("$3412".to_string()), value(0, "ptr le"));
+        assert_eq!(Some("1".to_string()), value(2, "f32 le"));
+        assert_eq!(Some("-32705".to_string()), value(4, "i16 be"));
+        assert_eq!(Some("80 - -".to_string()), value(4, "bcd"));
+        assert_eq!(None, value(4, "u64 be"));
+        assert_eq!(Some("-63".to_string()), value(8, "i8"));
+        assert_eq!(Some("'A' (EBCDIC)".to_string()), value(8, "char"));
+        assert!(inspect(&data, 9, &Charset::ascii()).is_empty());
+    }
+}
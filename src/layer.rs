@@ -0,0 +1,184 @@
+use annotation::{Annotation, AnnotationStore};
+
+/// Number of layers the viewer can display at once; this matches the size of
+/// the `layer_colors` array in the fragment shader.
+pub const MAX_LAYERS : usize = 16;
+
+static PALETTE : [(f32, f32, f32); 8] = [
+    (0.0, 0.8, 0.2),
+    (1.0, 0.6, 0.0),
+    (0.0, 0.7, 1.0),
+    (0.9, 0.2, 0.9),
+    (1.0, 1.0, 0.2),
+    (0.4, 0.4, 1.0),
+    (1.0, 0.3, 0.3),
+    (0.6, 1.0, 0.8),
+];
+
+/// An annotation store as displayed in the viewer.
+pub struct Layer {
+    pub store : AnnotationStore,
+    pub color : (f32, f32, f32),
+    pub visible : bool,
+}
+
+impl Layer {
+    pub fn title(&self) -> &str { self.store.title() }
+}
+
+/// The annotation layers of a view, from the bottom of the z-order to the top.
+pub struct LayerStack {
+    layers : Vec<Layer>,
+    next_color : usize,
+}
+
+impl Default for LayerStack {
+    fn default() -> Self { LayerStack::new() }
+}
+
+impl LayerStack {
+    pub fn new() -> LayerStack {
+        LayerStack { layers : Vec::new(), next_color : 0 }
+    }
+
+    /// Add a store as a visible layer on top of the others. A store with the
+    /// same title as an existing layer replaces that layer's annotations and
+    /// keeps its colour, visibility and place in the z-order.
+    /// Returns the index of the layer, or None if there is no room for another.
+    pub fn add(&mut self, store : AnnotationStore) -> Option<usize> {
+        if let Some(idx) = self.find(store.title()) {
+            self.layers[idx].store = store;
+            return Some(idx);
+        }
+        if self.layers.len() >= MAX_LAYERS {
+            return None;
+        }
+        let color = PALETTE[self.next_color % PALETTE.len()];
+        self.next_color += 1;
+        self.layers.push(Layer { store, color, visible : true });
+        Some(self.layers.len() - 1)
+    }
+
+    /// Put a fully specified layer on top of the others, as when restoring a
+    /// saved project. Returns None if there is no room for another layer.
+    pub fn push(&mut self, layer : Layer) -> Option<usize> {
+        if self.layers.len() >= MAX_LAYERS {
+            return None;
+        }
+        self.next_color += 1;
+        self.layers.push(layer);
+        Some(self.layers.len() - 1)
+    }
+
+    pub fn find(&self, title : &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.title() == title)
+    }
+
+    pub fn remove(&mut self, idx : usize) -> Layer {
+        self.layers.remove(idx)
+    }
+
+    pub fn get(&self, idx : usize) -> Option<&Layer> { self.layers.get(idx) }
+
+    pub fn get_mut(&mut self, idx : usize) -> Option<&mut Layer> { self.layers.get_mut(idx) }
+
+    pub fn len(&self) -> usize { self.layers.len() }
+
+    pub fn is_empty(&self) -> bool { self.layers.is_empty() }
+
+    /// Iterate from the bottom layer to the top one.
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Layer> { self.layers.iter() }
+
+    /// Move a layer one step up the z-order, returning its new index.
+    pub fn raise(&mut self, idx : usize) -> usize {
+        if idx + 1 < self.layers.len() {
+            self.layers.swap(idx, idx + 1);
+            idx + 1
+        } else {
+            idx
+        }
+    }
+
+    /// Move a layer one step down the z-order, returning its new index.
+    pub fn lower(&mut self, idx : usize) -> usize {
+        if idx > 0 && idx < self.layers.len() {
+            self.layers.swap(idx, idx - 1);
+            idx - 1
+        } else {
+            idx
+        }
+    }
+
+    /// Fill `buf` with, for each byte, one more than the index of the topmost
+    /// visible layer annotating it, or 0 where no visible layer does.
+    pub fn paint(&self, buf : &mut [u8]) {
+        for b in buf.iter_mut() { *b = 0; }
+        for (idx, layer) in self.layers.iter().enumerate() {
+            if !layer.visible { continue; }
+            for a in layer.store.iter() {
+                let (start, end) = a.span();
+                let end = end.min(buf.len());
+                if start < end {
+                    for b in &mut buf[start..end] { *b = (idx + 1) as u8; }
+                }
+            }
+        }
+    }
+
+    /// The annotations of every visible layer containing the given point,
+    /// topmost layer first.
+    pub fn query(&self, point : usize) -> Vec<(&Layer, &dyn Annotation)> {
+        let mut hits = Vec::new();
+        for layer in self.layers.iter().rev().filter(|l| l.visible) {
+            for a in layer.store.query(point) {
+                hits.push((layer, a));
+            }
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use annotation::CStringAnnotationEngine;
+    use annotation::AnnotationEngine;
+
+    static ASCII_TEST : &[u8] = include_bytes!("../sample_binaries/strings-test.bin");
+
+    fn strings(title : &str) -> AnnotationStore {
+        let mut store = AnnotationStore::new(title);
+        let built = CStringAnnotationEngine::default().build_default(ASCII_TEST).unwrap();
+        store.extend(built);
+        store
+    }
+
+    #[test]
+    fn layer_painting() {
+        let mut layers = LayerStack::new();
+        assert_eq!(Some(0), layers.add(strings("a")));
+        assert_eq!(Some(1), layers.add(strings("b")));
+        let mut buf = vec![0u8; ASCII_TEST.len()];
+        layers.paint(&mut buf);
+        // "string one" starts at 34; the top layer wins
+        assert_eq!(0, buf[33]);
+        assert_eq!(2, buf[34]);
+        assert_eq!(2, layers.query(34).len());
+        assert_eq!("b", layers.query(34)[0].0.title());
+
+        assert_eq!(0, layers.lower(1));
+        layers.paint(&mut buf);
+        assert_eq!(2, buf[34]);
+        assert_eq!("a", layers.get(1).unwrap().title());
+
+        layers.get_mut(1).unwrap().visible = false;
+        layers.paint(&mut buf);
+        assert_eq!(1, buf[34]);
+        assert_eq!(1, layers.query(34).len());
+
+        // re-adding a title replaces the layer in place
+        assert_eq!(Some(1), layers.add(strings("a")));
+        assert_eq!(2, layers.len());
+        assert!(!layers.get(1).unwrap().visible);
+    }
+}
@@ -0,0 +1,278 @@
+/// Largest group of bytes a byte order can rearrange.
+pub const MAX_GROUP : usize = 8;
+
+/// Order in which the bytes of each word are displayed. Data is shown as
+/// stored (big endian) unless the bytes of each group are rearranged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteOrder {
+    Big,
+    /// bytes of each 16-bit word swapped
+    Little16,
+    /// bytes of each 32-bit word reversed
+    Little32,
+    /// bytes of each 64-bit word reversed
+    Little64,
+    /// display position i of each group of `len` bytes shows stored byte `perm[i]`
+    Custom { perm : [u8; MAX_GROUP], len : usize },
+}
+
+impl ByteOrder {
+    /// A custom order, from the stored byte shown at each position of a group.
+    pub fn custom(perm : &[u8]) -> Result<ByteOrder, String> {
+        let len = perm.len();
+        if len == 0 || len > MAX_GROUP {
+            return Err(format!("a byte order covers 1 to {} bytes", MAX_GROUP));
+        }
+        let mut seen = [false; MAX_GROUP];
+        for &b in perm {
+            if b as usize >= len || seen[b as usize] {
+                return Err("a byte order must use each of 0..n exactly once".to_string());
+            }
+            seen[b as usize] = true;
+        }
+        let mut p = [0u8; MAX_GROUP];
+        p[..len].copy_from_slice(perm);
+        Ok(ByteOrder::Custom { perm : p, len })
+    }
+
+    /// Parse `be`, `le16`, `le32`, `le64`, or a custom order given as the
+    /// digits of the permutation, such as `1032` (PDP-11 order).
+    pub fn from_name(name : &str) -> Result<ByteOrder, String> {
+        match name.to_lowercase().as_str() {
+            "be" | "big" => Ok(ByteOrder::Big),
+            "le16" => Ok(ByteOrder::Little16),
+            "le32" | "le" => Ok(ByteOrder::Little32),
+            "le64" => Ok(ByteOrder::Little64),
+            other => {
+                let digits : Option<Vec<u8>> = other.chars().filter(|&c| c != ',' && c != ' ')
+                    .map(|c| c.to_digit(10).map(|d| d as u8)).collect();
+                match digits {
+                    Some(d) => ByteOrder::custom(&d),
+                    None => Err(format!("unknown byte order '{}'", name)),
+                }
+            }
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            ByteOrder::Big => "BE".to_string(),
+            ByteOrder::Little16 => "LE16".to_string(),
+            ByteOrder::Little32 => "LE32".to_string(),
+            ByteOrder::Little64 => "LE64".to_string(),
+            ByteOrder::Custom { .. } => self.permutation().iter().map(|b| b.to_string()).collect(),
+        }
+    }
+
+    /// The stored byte shown at each position of a group.
+    pub fn permutation(&self) -> Vec<u8> {
+        match *self {
+            ByteOrder::Big => vec![0],
+            ByteOrder::Little16 => vec![1, 0],
+            ByteOrder::Little32 => vec![3, 2, 1, 0],
+            ByteOrder::Little64 => vec![7, 6, 5, 4, 3, 2, 1, 0],
+            ByteOrder::Custom { perm, len } => perm[..len].to_vec(),
+        }
+    }
+
+    /// The order after this one when cycling through them; the custom order,
+    /// if there is one, comes after LE64.
+    pub fn next(&self, custom : Option<ByteOrder>) -> ByteOrder {
+        match *self {
+            ByteOrder::Big => ByteOrder::Little16,
+            ByteOrder::Little16 => ByteOrder::Little32,
+            ByteOrder::Little32 => ByteOrder::Little64,
+            ByteOrder::Little64 => custom.unwrap_or(ByteOrder::Big),
+            ByteOrder::Custom { .. } => ByteOrder::Big,
+        }
+    }
+
+    /// The stored byte shown at the given display position.
+    pub fn stored_index(&self, pos : usize) -> usize {
+        let perm = self.permutation();
+        let g = perm.len();
+        pos - pos % g + perm[pos % g] as usize
+    }
+
+    /// The display position of the given stored byte.
+    pub fn display_index(&self, idx : usize) -> usize {
+        let perm = self.permutation();
+        let g = perm.len();
+        let within = perm.iter().position(|&b| b as usize == idx % g).unwrap();
+        idx - idx % g + within
+    }
+
+    /// The bytes shown at display positions `start..end`, in display order.
+    /// Positions whose byte lies past the end of the data are skipped.
+    pub fn reorder(&self, data : &[u8], start : usize, end : usize) -> Vec<u8> {
+        (start..end).map(|p| self.stored_index(p)).filter_map(|i| data.get(i).cloned()).collect()
+    }
+}
+
+/// Geometry of the bitmap view: how the bytes of the image are laid out as
+/// columns of pixels. This mirrors the math in the fragment shader, so that
+/// screen positions can be mapped back onto the data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    /// width, in bits, of each column
+    pub word : u32,
+    /// bits per pixel (1 for bitmap, 8 for bytemap, etc)
+    pub bpp : u8,
+    /// height, in rows, of each column
+    pub col_height : u32,
+    /// spacing, in pixels, between columns
+    pub spacing : u32,
+    /// offset before start of data to display, in bytes
+    pub data_offset : usize,
+    /// total length of data, in bytes
+    pub data_len : usize,
+    pub byte_order : ByteOrder,
+}
+
+impl Layout {
+    pub fn new(data_len : usize) -> Layout {
+        Layout {
+            word : 8,
+            bpp : 1,
+            col_height : 512,
+            spacing : 4,
+            data_offset : 0,
+            data_len,
+            byte_order : ByteOrder::Big,
+        }
+    }
+
+    /// Width of a column, in pixels.
+    pub fn col_width(&self) -> u32 { self.word / self.bpp as u32 }
+
+    /// Find the byte displayed at the given point. The point is in unzoomed
+    /// pixels relative to the upper left corner of the first column.
+    /// Returns None for points outside the data or in the gutters between columns.
+    pub fn byte_from_coords(&self, pos : (f64, f64)) -> Option<usize> {
+        let (x, y) = pos;
+        if x < 0.0 || y < 0.0 || y >= self.col_height as f64 {
+            return None;
+        }
+        let (x, y) = (x as u64, y as u64);
+        let cw = self.col_width() as u64;
+        let column = x / (cw + self.spacing as u64);
+        let el_in_row = x % (cw + self.spacing as u64);
+        if el_in_row >= cw {
+            return None;
+        }
+
+        let el_per_b = (8 / self.bpp) as u64;
+        let el_idx = (cw * self.col_height as u64 * column) + (y * cw) + el_in_row;
+        let idx = self.byte_order.stored_index((el_idx / el_per_b) as usize + self.data_offset);
+
+        if idx < self.data_len { Some(idx) } else { None }
+    }
+
+    /// Find where the given byte is displayed: the upper left corner of its
+    /// first pixel, in the same coordinates `byte_from_coords` takes. Returns
+    /// None for bytes before the data offset or past the end of the data.
+    pub fn coords_from_byte(&self, idx : usize) -> Option<(f64, f64)> {
+        let pos = self.byte_order.display_index(idx);
+        if idx >= self.data_len || pos < self.data_offset {
+            return None;
+        }
+        let cw = self.col_width() as u64;
+        let el_per_b = (8 / self.bpp) as u64;
+        let el_idx = (pos - self.data_offset) as u64 * el_per_b;
+        let per_column = cw * self.col_height as u64;
+        let column = el_idx / per_column;
+        let y = (el_idx % per_column) / cw;
+        let x = column * (cw + self.spacing as u64) + el_idx % cw;
+        Some((x as f64, y as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_from_coords() {
+        let mut l = Layout::new(4096);
+        l.spacing = 4;
+        l.col_height = 16;
+        // 1bpp, 8 bits per row: one byte per row
+        assert_eq!(Some(0), l.byte_from_coords((0.0, 0.0)));
+        assert_eq!(Some(0), l.byte_from_coords((7.5, 0.0)));
+        assert_eq!(Some(3), l.byte_from_coords((2.0, 3.0)));
+        assert_eq!(None, l.byte_from_coords((9.0, 3.0))); // gutter
+        assert_eq!(Some(16), l.byte_from_coords((12.0, 0.0)));
+        assert_eq!(None, l.byte_from_coords((0.0, 16.0)));
+        assert_eq!(None, l.byte_from_coords((-1.0, 0.0)));
+
+        // 8bpp: one pixel per byte, columns a single pixel wide
+        l.bpp = 8;
+        assert_eq!(Some(5), l.byte_from_coords((0.0, 5.0)));
+        assert_eq!(Some(16), l.byte_from_coords((5.0, 0.0)));
+
+        // the data offset shifts everything along
+        l.data_offset = 100;
+        assert_eq!(Some(105), l.byte_from_coords((0.0, 5.0)));
+        l.data_offset = 4090;
+        assert_eq!(None, l.byte_from_coords((0.0, 6.0)));
+
+        // little endian words show their bytes reversed
+        l.data_offset = 0;
+        l.byte_order = ByteOrder::Little32;
+        assert_eq!(Some(3), l.byte_from_coords((0.0, 0.0)));
+        assert_eq!(Some(4), l.byte_from_coords((0.0, 7.0)));
+    }
+
+    #[test]
+    fn coords_from_byte() {
+        let mut l = Layout::new(4096);
+        l.col_height = 16;
+        l.spacing = 3;
+        for &(word, bpp) in [(8, 1), (16, 1), (16, 2), (32, 4), (8, 8), (24, 8)].iter() {
+            l.word = word;
+            l.bpp = bpp;
+            for &order in [ByteOrder::Big, ByteOrder::Little32].iter() {
+                l.byte_order = order;
+                for idx in (5..4096).step_by(97) {
+                    let (x, y) = l.coords_from_byte(idx).unwrap();
+                    assert_eq!(Some(idx), l.byte_from_coords((x + 0.5, y + 0.5)));
+                }
+            }
+        }
+        l.data_offset = 100;
+        l.byte_order = ByteOrder::Big;
+        assert_eq!(None, l.coords_from_byte(99));
+        assert_eq!(Some((0.0, 0.0)), l.coords_from_byte(100));
+        assert_eq!(None, l.coords_from_byte(4096));
+    }
+
+    #[test]
+    fn byte_orders() {
+        let data = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        assert_eq!(vec![1, 0, 3, 2, 5], ByteOrder::Little16.reorder(&data, 0, 5));
+        // bytes 8 and 9 would show bytes 11 and 10 of a short final word
+        assert_eq!(vec![3, 2, 1, 0, 7, 6, 5, 4], ByteOrder::Little32.reorder(&data, 0, 10));
+        assert_eq!(vec![7, 6, 5, 4, 3, 2, 1, 0], ByteOrder::Little64.reorder(&data, 0, 8));
+        assert_eq!(vec![2, 3, 4], ByteOrder::Big.reorder(&data, 2, 5));
+
+        let pdp = ByteOrder::from_name("1032").unwrap();
+        assert_eq!("1032", pdp.name());
+        assert_eq!(vec![1, 0, 3, 2], pdp.reorder(&data, 0, 4));
+        assert_eq!(Ok(ByteOrder::Little16), ByteOrder::from_name("le16"));
+        assert!(ByteOrder::from_name("0012").is_err());
+        assert!(ByteOrder::from_name("wrong").is_err());
+
+        let odd = ByteOrder::custom(&[2, 0, 1]).unwrap();
+        for i in 0..12 {
+            assert_eq!(i, odd.stored_index(odd.display_index(i)));
+        }
+        let mut order = ByteOrder::Big;
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            seen.push(order.name());
+            order = order.next(Some(pdp));
+        }
+        assert_eq!(vec!["BE", "LE16", "LE32", "LE64", "1032"], seen);
+        assert_eq!(ByteOrder::Big, order);
+    }
+}
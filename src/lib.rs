@@ -0,0 +1,30 @@
+//! Analysis core of the ROM explorer.
+//!
+//! Everything in here is independent of OpenGL and GLFW, so it can be used
+//! from other tools and tested on machines without a GPU. The interactive
+//! viewer lives in the `romexp2` binary behind the `gui` feature.
+
+extern crate goblin;
+extern crate memmap;
+extern crate regex;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
+
+pub mod addrmap;
+pub mod annotation;
+pub mod charset;
+pub mod export;
+pub mod hexfile;
+pub mod image;
+pub mod inspect;
+pub mod layer;
+pub mod layout;
+pub mod nav;
+pub mod parse;
+pub mod project;
+pub mod report;
+pub mod search;
+pub mod selection;
+pub mod transform;
@@ -1,24 +1,40 @@
-extern crate memmap;
+extern crate romexp;
+#[cfg(feature = "gui")]
 extern crate glfw;
+#[cfg(feature = "gui")]
 extern crate gl;
-#[macro_use]
 extern crate clap;
 
-use clap::{Arg,App};
+use clap::{Arg,App,ArgMatches};
+#[cfg(feature = "gui")]
+use clap::ValueSource;
 
-use memmap::{Mmap, Protection};
+use romexp::addrmap::AddressMap;
+use romexp::annotation;
+use romexp::annotation::AnnotationEngine;
+use romexp::image::{Interleave, RomImage};
+use romexp::export;
+use romexp::layout::ByteOrder;
+use romexp::parse;
+use romexp::selection::Selection;
+use romexp::report;
+use romexp::transform::Pipeline;
 
-use std::str;
-use std::cmp;
+use std::borrow::Cow;
+use std::io;
+use std::process;
 
-mod annotation;
+#[cfg(feature = "gui")]
 mod viz;
+#[cfg(feature = "gui")]
 mod font;
+#[cfg(feature = "gui")]
 mod glutil;
-
-use glfw::{Action, Context, Key};
+#[cfg(feature = "gui")]
+mod prompt;
 
 fn main() {
+    let engine_ids : Vec<&'static str> = annotation::engines().iter().map(|e| e.id()).collect();
     let matches = App::new("ROM image explorer")
         .version("0.1")
         .author("phooky@gmail.com")
@@ -28,43 +44,304 @@ fn main() {
              .short('w')
              .long("wordsize")
              .takes_value(true)
+             .value_parser(parse::parse_u32)
              .default_value("1"))
         .arg(Arg::with_name("intercolumn")
              .help("space between columns")
              .long("intercolumn")
              .short('i')
              .takes_value(true)
+             .value_parser(parse::parse_u32)
              .default_value("0"))
         .arg(Arg::with_name("offset")
             .help("initial offset into binary blob")
             .short('o')
+            .long("offset")
             .takes_value(true)
+            .value_parser(parse::parse_usize)
             .default_value("0"))
+        .arg(Arg::with_name("end")
+            .help("end of the data to show (exclusive)")
+            .long("end")
+            .takes_value(true)
+            .value_parser(parse::parse_usize)
+            .conflicts_with("length"))
+        .arg(Arg::with_name("length")
+            .help("length of the data to show, from the offset")
+            .long("length")
+            .takes_value(true)
+            .value_parser(parse::parse_usize))
+        .arg(Arg::with_name("height")
+            .help("column height in pixels")
+            .long("height")
+            .takes_value(true)
+            .value_parser(parse::parse_u32)
+            .default_value("512"))
+        .arg(Arg::with_name("bpp")
+            .help("bits per pixel: 1, 2, 4 or 8")
+            .long("bpp")
+            .takes_value(true)
+            .value_parser(parse_bpp)
+            .default_value("1"))
+        .arg(Arg::with_name("zoom")
+            .help("initial zoom factor")
+            .long("zoom")
+            .takes_value(true)
+            .value_parser(parse_zoom)
+            .default_value("1"))
+        .arg(Arg::with_name("byte-order")
+            .help("initial byte order: be, le16, le32, le64, or a custom order such as 1032")
+            .long("byte-order")
+            .takes_value(true)
+            .default_value("be"))
+        .arg(Arg::with_name("charset")
+            .help("charset for the inspector: ascii, petscii, ebcdic or a charset file")
+            .long("charset")
+            .takes_value(true)
+            .default_value("ascii"))
+        .arg(Arg::with_name("pointer")
+            .help("how to read pointers when following them, e.g. \"width=4 order=be base=0xf80000\"")
+            .long("pointer")
+            .takes_value(true))
+        .arg(Arg::with_name("base")
+            .help("address the start of the file is loaded at")
+            .long("base")
+            .takes_value(true)
+            .global(true)
+            .value_parser(parse::parse_number))
+        .arg(Arg::with_name("map")
+            .help("memory map file, with lines of \"first-last address [bank]\" mapping file ranges to addresses")
+            .long("map")
+            .takes_value(true)
+            .global(true)
+            .conflicts_with("base"))
+        .arg(Arg::with_name("no-project")
+            .help("don't load or save the project file kept beside the ROM")
+            .long("no-project"))
+        .arg(Arg::with_name("interleave")
+            .help("how to merge several ROM files: concat, byte, word, long, or a number of bytes from each in turn")
+            .long("interleave")
+            .takes_value(true)
+            .global(true))
+        .arg(Arg::with_name("fill")
+            .help("byte to fill the gaps between the records of a hex file with")
+            .long("fill")
+            .takes_value(true)
+            .global(true)
+            .default_value("0xff")
+            .value_parser(parse::parse_u8))
+        .arg(Arg::with_name("transform")
+            .help("transforms to apply to the data, such as \"bits=76543201 xor=a5\"")
+            .long("transform")
+            .takes_value(true)
+            .global(true))
         .arg(Arg::with_name("ROM")
-            .help("ROM file to analyze")
-            .required(true))
+            .help("ROM file(s) to analyze; several are merged as given by --interleave")
+            .required(true)
+            .multiple_values(true))
+        .subcommand_negates_reqs(true)
+        .subcommand(App::new("annotate")
+            .about("Run annotation engines and print the results without opening a window")
+            .arg(Arg::with_name("engine")
+                 .help("Annotation engine(s) to run")
+                 .short('e')
+                 .long("engine")
+                 .takes_value(true)
+                 .multiple_occurrences(true)
+                 .use_value_delimiter(true)
+                 .possible_values(engine_ids)
+                 .default_value("strings"))
+            .arg(Arg::with_name("param")
+                 .help("Engine parameter, as name=value or engine.name=value (see the engines command)")
+                 .short('p')
+                 .long("param")
+                 .takes_value(true)
+                 .multiple_occurrences(true))
+            .arg(Arg::with_name("format")
+                 .help("Output format")
+                 .short('f')
+                 .long("format")
+                 .takes_value(true)
+                 .possible_values(["text", "csv", "json"])
+                 .default_value("text"))
+            .arg(Arg::with_name("ROM")
+                 .help("ROM file(s) to analyze; several are merged as given by --interleave")
+                 .required(true)
+                 .multiple_values(true)))
+        .subcommand(App::new("export")
+            .about("Write part of the ROM to a file: raw, C array, Intel HEX, S-record or hex dump")
+            .arg(Arg::with_name("start")
+                 .help("first byte to export")
+                 .long("start")
+                 .takes_value(true)
+                 .value_parser(parse::parse_usize))
+            .arg(Arg::with_name("end")
+                 .help("end of the range to export (exclusive)")
+                 .long("end")
+                 .takes_value(true)
+                 .value_parser(parse::parse_usize)
+                 .conflicts_with("length"))
+            .arg(Arg::with_name("length")
+                 .help("number of bytes to export")
+                 .long("length")
+                 .takes_value(true)
+                 .value_parser(parse::parse_usize))
+            .arg(Arg::with_name("selection")
+                 .help("export the selection saved in the ROM's project file")
+                 .long("selection")
+                 .conflicts_with_all(&["start", "end", "length"]))
+            .arg(Arg::with_name("format")
+                 .help("output format; by default, taken from the output file's extension")
+                 .short('f')
+                 .long("format")
+                 .takes_value(true)
+                 .possible_values(export::FORMAT_NAMES))
+            .arg(Arg::with_name("byte-order")
+                 .help("rearrange the bytes into this order first: le16, le32, le64 or a custom order")
+                 .long("byte-order")
+                 .takes_value(true))
+            .arg(Arg::with_name("ROM")
+                 .help("ROM file to export from")
+                 .required(true))
+            .arg(Arg::with_name("OUTPUT")
+                 .help("file to write")
+                 .required(true)))
+        .subcommand(App::new("engines")
+            .about("List the annotation engines and their parameters"))
+        .subcommand(App::new("free")
+            .about("Report how much of the ROM is padding, by fill value")
+            .arg(Arg::with_name("min-length")
+                 .help("Shortest run of fill, in bytes, to count")
+                 .long("min-length")
+                 .takes_value(true)
+                 .value_parser(parse::parse_usize)
+                 .default_value("16"))
+            .arg(Arg::with_name("ROM")
+                 .help("ROM file(s) to analyze; several are merged as given by --interleave")
+                 .required(true)
+                 .multiple_values(true)))
         .get_matches();
 
-    let rom_path = matches.value_of("ROM").unwrap();
-    let rom = match Mmap::open_path(rom_path,Protection::Read) {
+    if let Some(sub) = matches.subcommand_matches("annotate") {
+        annotate(sub);
+        return;
+    }
+    if let Some(sub) = matches.subcommand_matches("export") {
+        export_range(sub);
+        return;
+    }
+    if matches.subcommand_matches("engines").is_some() {
+        list_engines();
+        return;
+    }
+    if let Some(sub) = matches.subcommand_matches("free") {
+        free(sub);
+        return;
+    }
+
+    view(&matches);
+}
+
+#[cfg(not(feature = "gui"))]
+fn view(_matches : &ArgMatches) {
+    eprintln!("romexp2 was built without the `gui` feature, which the graphical view needs; see --help for the commands that work without it.");
+    process::exit(1);
+}
+
+#[cfg(feature = "gui")]
+fn view(matches : &ArgMatches) {
+    use glfw::Context;
+    use romexp::project;
+    use std::cmp;
+    use std::fs;
+
+    let rom = match open_rom(matches) {
         Ok(r) => r,
-        Err(e) => { println!("Could not open {}: {}",rom_path,e); return; },
+        Err(e) => { println!("{}", e); return; },
     };
-    let word = value_t_or_exit!(matches,"wordsize",u32) * 8;
-    println!("Opened {}; size {} bytes",rom_path,rom.len());
+    let rom_path = rom.path();
+    let word = *matches.get_one::<u32>("wordsize").unwrap() * 8;
+    println!("Opened {}; size {} bytes",rom_path.display(),rom.len());
 
-    let height = 512;
-    let spacing = value_t_or_exit!(matches,"intercolumn",u32); // default spacing in px
-    let offset = value_t_or_exit!(matches,"offset",usize); // initial offset
+    let height = *matches.get_one::<u32>("height").unwrap();
+    let bpp = *matches.get_one::<u8>("bpp").unwrap();
+    let spacing = *matches.get_one::<u32>("intercolumn").unwrap(); // default spacing in px
+    let offset = *matches.get_one::<usize>("offset").unwrap(); // initial offset
+    let end = match (matches.get_one::<usize>("end"), matches.get_one::<usize>("length")) {
+        (Some(&end), _) => end,
+        (_, Some(&len)) => offset.saturating_add(len),
+        _ => rom.len(),
+    };
+    if word == 0 || height == 0 || end <= offset {
+        println!("Nothing to show: the word size, height and length must be more than zero");
+        return;
+    }
     let bytes_per_column = (word/8)*height;
-    let columns = rom.len() as u32 / bytes_per_column;
-    let width = cmp::max(512,columns*(word+spacing));
+    let columns = cmp::min(rom.len(), end) as u32 / bytes_per_column;
+    let width = cmp::max(512,columns*(word/bpp as u32+spacing));
 
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
-    let mut viz = viz::Visualizer::new(&mut glfw, (width, height), unsafe { rom.as_slice() });
-    viz.set_word(word);
-    viz.set_spacing(spacing);
-    viz.set_offset(offset);
+    let mut viz = viz::Visualizer::new(&mut glfw, (width, cmp::min(height, 1024)), rom.data());
+    viz.set_end(end);
+    let byte_order = match ByteOrder::from_name(matches.value_of("byte-order").unwrap()) {
+        Ok(order) => order,
+        Err(e) => { println!("{}", e); return; },
+    };
+    match romexp::charset::Charset::load(matches.value_of("charset").unwrap()) {
+        Ok(charset) => viz.set_charset(charset),
+        Err(e) => { println!("{}", e); return; },
+    }
+    match address_map(matches, &rom) {
+        Ok(map) => viz.set_address_map(map.unwrap_or_default()),
+        Err(e) => { println!("{}", e); return; },
+    }
+    match romexp::nav::PointerFormat::parse(matches.value_of("pointer").unwrap_or("")) {
+        Ok(pointer) => viz.set_pointer_format(pointer),
+        Err(e) => { println!("{}", e); return; },
+    }
+    let transform = match Pipeline::parse(matches.value_of("transform").unwrap_or("")) {
+        Ok(transform) => transform,
+        Err(e) => { println!("{}", e); return; },
+    };
+    let project_path = project::project_path(&rom_path);
+    let use_project = !matches.is_present("no-project");
+    let mut restored = false;
+    if use_project && project_path.exists() {
+        match project::Project::load(&project_path) {
+            Ok(ref p) if p.matches(rom.data()) => {
+                viz.restore(p);
+                restored = true;
+            },
+            Ok(_) => {
+                // Keep the old project out of the way rather than overwriting it on exit.
+                let mut stale = project_path.clone().into_os_string();
+                stale.push(".stale");
+                println!("{} was made for a different ROM; moving it to {}",
+                         project_path.display(), stale.to_string_lossy());
+                if let Err(e) = fs::rename(&project_path, &stale) {
+                    println!("Could not move stale project: {}", e);
+                    return;
+                }
+            },
+            Err(e) => {
+                println!("Could not load {}: {} (use --no-project to ignore it)",
+                         project_path.display(), e);
+                return;
+            },
+        }
+    }
+    // A restored project wins over the defaults, but not over options given
+    // on the command line.
+    let given = |name : &str| !restored || matches.value_source(name) == Some(ValueSource::CommandLine);
+    if given("wordsize") { viz.set_word(word); }
+    if given("bpp") { viz.set_bpp(bpp); }
+    if given("height") { viz.set_height(height); }
+    if given("intercolumn") { viz.set_spacing(spacing); }
+    if given("offset") { viz.set_offset(offset); }
+    if given("zoom") { viz.set_zoom(*matches.get_one::<f32>("zoom").unwrap()); }
+    if given("byte-order") { viz.set_byte_order(byte_order); }
+    if given("transform") { viz.set_transform(transform); }
+    viz.set_image(&rom);
     viz.window.make_current();
     glfw.set_swap_interval(glfw::SwapInterval::Sync(1));
     while !viz.window.should_close() {
@@ -72,5 +349,217 @@ fn main() {
         glfw.wait_events();
         viz.handle_events();
     }
+    if use_project {
+        if let Err(e) = viz.project().save(&project_path) {
+            println!("Could not save {}: {}", project_path.display(), e);
+        }
+    }
+}
+
+
+fn parse_bpp(text : &str) -> Result<u8, String> {
+    match parse::parse_number(text)? {
+        n @ 1 | n @ 2 | n @ 4 | n @ 8 => Ok(n as u8),
+        _ => Err("bits per pixel must be 1, 2, 4 or 8".to_string()),
+    }
+}
+
+fn parse_zoom(text : &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(z) if z > 0.0 && z.is_finite() => Ok(z),
+        _ => Err(format!("'{}' is not a zoom factor", text)),
+    }
 }
 
+fn list_engines() {
+    for engine in annotation::engines() {
+        println!("{:<10} {}", engine.id(), engine.description());
+        for p in engine.parameters() {
+            println!("    {:<12} {} (default {})", p.name, p.help, p.default);
+        }
+    }
+}
+
+/// Share out `name=value` assignments among the engines: `engine.name=value`
+/// goes to the named engine, and a plain `name=value` to every engine that
+/// takes that parameter.
+fn engine_params(engines : &[Box<dyn AnnotationEngine>], assignments : &[&str])
+                 -> Result<Vec<annotation::Params>, String> {
+    let mut params = vec![annotation::Params::new(); engines.len()];
+    for a in assignments {
+        let (name, value) = match a.find('=') {
+            Some(i) => (&a[..i], &a[i + 1..]),
+            None => return Err(format!("parameter '{}' should be name=value", a)),
+        };
+        let (target, name) = match name.find('.') {
+            Some(i) => (Some(&name[..i]), &name[i + 1..]),
+            None => (None, name),
+        };
+        let mut used = false;
+        for (engine, p) in engines.iter().zip(params.iter_mut()) {
+            let takes = engine.parameters().iter().any(|info| info.name == name);
+            match target {
+                Some(id) if id == engine.id() => { p.set(name, value); used = true; },
+                None if takes => { p.set(name, value); used = true; },
+                _ => (),
+            }
+        }
+        if !used {
+            return Err(format!("no engine being run takes the parameter '{}'", a));
+        }
+    }
+    Ok(params)
+}
+
+/// Headless mode: run the requested engines over the ROM and print every annotation.
+fn annotate(matches : &ArgMatches) {
+    use std::io::{IsTerminal, Write};
+    use std::sync::atomic::AtomicBool;
+    let rom = match open_rom(matches) {
+        Ok(r) => r,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let data = match transformed(&rom, matches) {
+        Ok(d) => d,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let format = report::Format::from_name(matches.value_of("format").unwrap()).unwrap();
+    let engines : Vec<Box<dyn AnnotationEngine>> = matches.values_of("engine").unwrap()
+        .map(|id| annotation::engine_by_id(id).unwrap())
+        .collect();
+    let assignments : Vec<&str> = matches.values_of("param").map_or(Vec::new(), |v| v.collect());
+    let params = match engine_params(&engines, &assignments) {
+        Ok(p) => p,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let show_progress = io::stderr().is_terminal();
+    let never_cancel = AtomicBool::new(false);
+    let mut stores = Vec::new();
+    if rom.is_pieced() {
+        stores.push(rom.files_layer());
+    }
+    for (engine, p) in engines.iter().zip(params.iter()) {
+        let mut report = |done : usize, total : usize| {
+            if show_progress {
+                eprint!("\r{}: {}%", engine.id(), done * 100 / total.max(1));
+                let _ = io::stderr().flush();
+            }
+        };
+        let result = engine.build_annotations(&data, p, &mut annotation::Progress::new(&mut report, &never_cancel));
+        if show_progress { eprint!("\r\x1b[K"); }
+        match result {
+            Ok(store) => stores.push(store),
+            Err(e) => { eprintln!("{}: {}", engine.id(), e); process::exit(1); },
+        }
+    }
+    let stdout = io::stdout();
+    let map = match address_map(matches, &rom) {
+        Ok(m) => m,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    if let Err(e) = report::write_report(&mut stdout.lock(), &stores, format, map.as_ref()) {
+        eprintln!("Could not write annotations: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Open the ROM named on the command line, or merge the ROMs.
+fn open_rom(matches : &ArgMatches) -> Result<RomImage, String> {
+    let paths : Vec<&str> = matches.values_of("ROM").unwrap().collect();
+    let interleave = match matches.value_of("interleave") {
+        Some(name) => Interleave::from_name(name)?,
+        None if paths.len() > 1 => return Err(format!("Give --interleave to say how to merge the {} files", paths.len())),
+        None => Interleave::Concat,
+    };
+    let fill = *matches.get_one::<u8>("fill").unwrap();
+    RomImage::open_many(&paths, interleave, fill).map_err(|e| format!("Could not open {}: {}", paths.join(", "), e))
+}
+
+/// The data with the transforms given by `--transform` applied.
+fn transformed<'a>(rom : &'a RomImage, matches : &ArgMatches) -> Result<Cow<'a, [u8]>, String> {
+    let transform = Pipeline::parse(matches.value_of("transform").unwrap_or(""))?;
+    Ok(if transform.is_empty() { Cow::Borrowed(rom.data()) } else { Cow::Owned(transform.apply(rom.data())) })
+}
+
+/// The address map given with `--base` or `--map`, or else the load address
+/// from a hex file, if there is any of them.
+fn address_map(matches : &ArgMatches, rom : &RomImage) -> Result<Option<AddressMap>, String> {
+    match matches.value_of("map") {
+        Some(path) => AddressMap::load(path).map(Some),
+        None => Ok(matches.get_one::<u64>("base").cloned().or_else(|| rom.base()).map(AddressMap::flat)),
+    }
+}
+
+/// Headless mode: write a range of the ROM to a file.
+fn export_range(matches : &ArgMatches) {
+    use romexp::project;
+    let rom = match open_rom(matches) {
+        Ok(r) => r,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let selection = if matches.is_present("selection") {
+        let path = project::project_path(rom.path());
+        match project::Project::load(&path) {
+            Ok(ref p) if !p.matches(rom.data()) => {
+                eprintln!("{} was made for a different ROM", path.display()); process::exit(1);
+            },
+            Ok(project::Project { selection : Some((start, end)), .. }) => Selection::new(start, end),
+            Ok(_) => { eprintln!("Nothing is selected in {}", path.display()); process::exit(1); },
+            Err(e) => { eprintln!("Could not load {}: {}", path.display(), e); process::exit(1); },
+        }
+    } else {
+        let start = matches.get_one::<usize>("start").cloned().unwrap_or(0);
+        let end = match (matches.get_one::<usize>("end"), matches.get_one::<usize>("length")) {
+            (Some(&end), _) => end,
+            (_, Some(&len)) => start.saturating_add(len),
+            _ => rom.len(),
+        }.min(rom.len());
+        if end <= start {
+            eprintln!("Nothing to export: the range 0x{:x}-0x{:x} is empty", start, end);
+            process::exit(1);
+        }
+        Selection::new(start, end - 1)
+    };
+    let order = match matches.value_of("byte-order").map(ByteOrder::from_name) {
+        Some(Ok(order)) => Some(order),
+        Some(Err(e)) => { eprintln!("{}", e); process::exit(1); },
+        None => None,
+    };
+    let format = matches.value_of("format").map(|f| export::ExportFormat::from_name(f).unwrap());
+    let address = match address_map(matches, &rom) {
+        Ok(map) => map.and_then(|m| m.address_of(selection.start)).map_or(selection.start as u64, |a| a.0),
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let data = match transformed(&rom, matches) {
+        Ok(d) => export::selected_data(&d, selection, order),
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let out_path = matches.value_of("OUTPUT").unwrap();
+    if let Err(e) = export::export_file(out_path, &data, address, format) {
+        eprintln!("Could not export to {}: {}", out_path, e);
+        process::exit(1);
+    }
+}
+
+/// Headless mode: total up the fill regions in the ROM by fill pattern.
+fn free(matches : &ArgMatches) {
+    use annotation::fill::{FillAnnotationEngine, pattern_str, summarize};
+    let rom = match open_rom(matches) {
+        Ok(r) => r,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let min_len = *matches.get_one::<usize>("min-length").unwrap();
+    let data = match transformed(&rom, matches) {
+        Ok(d) => d,
+        Err(e) => { eprintln!("{}", e); process::exit(1); },
+    };
+    let fills = FillAnnotationEngine::new(min_len, 4).find_fills(&data);
+    let totals = summarize(&fills);
+    for t in &totals {
+        println!("{:<12} {:>10} bytes in {} region{}", pattern_str(&t.pattern), t.bytes,
+                 t.regions, if t.regions == 1 { "" } else { "s" });
+    }
+    let total : usize = totals.iter().map(|t| t.bytes).sum();
+    println!("{:<12} {:>10} bytes of {} ({:.1}%)", "total", total, rom.len(),
+             100.0 * total as f64 / rom.len().max(1) as f64);
+}
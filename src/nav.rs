@@ -0,0 +1,170 @@
+//! Following pointers through the image, and the history of where we've been.
+
+use std::fmt;
+
+use addrmap::AddressMap;
+use annotation::{Parameter, Params};
+
+/// Most places kept in each direction of the history
+pub const MAX_HISTORY : usize = 256;
+
+static POINTER_PARAMS : [Parameter; 3] = [
+    Parameter { name : "width", default : "2", help : "pointer size in bytes, 1 to 8" },
+    Parameter { name : "order", default : "le", help : "byte order: le or be" },
+    Parameter { name : "base", default : "", help : "address the start of the image is loaded at; by default, use the address map" },
+];
+
+/// How pointers are stored: their size, byte order, and the address the
+/// image is loaded at, if that isn't taken from the address map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointerFormat {
+    pub width : usize,
+    pub little : bool,
+    pub base : Option<usize>,
+}
+
+impl Default for PointerFormat {
+    /// 16-bit little endian, as on the 6502 and Z80.
+    fn default() -> PointerFormat {
+        PointerFormat { width : 2, little : true, base : None }
+    }
+}
+
+impl fmt::Display for PointerFormat {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "width={} order={}", self.width, if self.little { "le" } else { "be" })?;
+        match self.base {
+            Some(base) => write!(f, " base=0x{:x}", base),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PointerFormat {
+    /// The parameters `parse` accepts.
+    pub fn parameters() -> &'static [Parameter] { &POINTER_PARAMS }
+
+    /// Read a format given as `name=value` parameters, such as
+    /// `width=4 order=be base=0xf80000`. Missing ones keep their defaults.
+    pub fn parse(text : &str) -> Result<PointerFormat, String> {
+        let params = Params::parse(text).map_err(|e| e.to_string())?;
+        params.check(&POINTER_PARAMS).map_err(|e| e.to_string())?;
+        let default = PointerFormat::default();
+        let width = params.get_usize("width", default.width).map_err(|e| e.to_string())?;
+        if width == 0 || width > 8 {
+            return Err(format!("pointers must be 1 to 8 bytes wide, not {}", width));
+        }
+        let little = match params.get_str("order").unwrap_or("le") {
+            "le" => true,
+            "be" => false,
+            o => return Err(format!("unknown byte order {}; use le or be", o)),
+        };
+        let base = match params.get_str("base") {
+            Some(_) => Some(params.get_usize("base", 0).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        Ok(PointerFormat { width, little, base })
+    }
+
+    /// The pointer stored at `pos`, if it lies wholly within the data.
+    pub fn read(&self, data : &[u8], pos : usize) -> Option<u64> {
+        let bytes = data.get(pos..pos.checked_add(self.width)?)?;
+        let fold = |acc : u64, &b : &u8| (acc << 8) | b as u64;
+        Some(if self.little { bytes.iter().rev().fold(0, fold) } else { bytes.iter().fold(0, fold) })
+    }
+
+    /// Where in the image a pointer points, if it points into it at all.
+    /// Without a base of its own, the pointer is looked up in the address
+    /// map, preferring the bank it was found in.
+    pub fn target(&self, pointer : u64, len : usize, map : &AddressMap, bank : Option<u32>) -> Option<usize> {
+        let offset = match self.base {
+            Some(base) => pointer.checked_sub(base as u64)? as usize,
+            None => map.offset_of(pointer, bank).or_else(|| map.offset_of(pointer, None))?,
+        };
+        if offset < len { Some(offset) } else { None }
+    }
+}
+
+/// Back and forward stacks of visited places, as in a web browser.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    back : Vec<usize>,
+    forward : Vec<usize>,
+}
+
+impl History {
+    pub fn new() -> History { History::default() }
+
+    /// Note that we're leaving `from` for somewhere new.
+    pub fn visit(&mut self, from : usize) {
+        if self.back.len() == MAX_HISTORY { self.back.remove(0); }
+        self.back.push(from);
+        self.forward.clear();
+    }
+
+    /// Step back from `current`, returning where to go.
+    pub fn back(&mut self, current : usize) -> Option<usize> {
+        let to = self.back.pop()?;
+        self.forward.push(current);
+        Some(to)
+    }
+
+    /// Step forward again from `current`, returning where to go.
+    pub fn forward(&mut self, current : usize) -> Option<usize> {
+        let to = self.forward.pop()?;
+        self.back.push(current);
+        Some(to)
+    }
+
+    pub fn can_go_back(&self) -> bool { !self.back.is_empty() }
+
+    pub fn can_go_forward(&self) -> bool { !self.forward.is_empty() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pointers() {
+        let f = PointerFormat::parse("width=4 order=be base=0xf8000").unwrap();
+        assert_eq!(PointerFormat { width : 4, little : false, base : Some(0xf8000) }, f);
+        assert_eq!(f, PointerFormat::parse(&f.to_string()).unwrap());
+        assert_eq!(PointerFormat::default(), PointerFormat::parse("").unwrap());
+        assert!(PointerFormat::parse("width=9").is_err());
+        assert!(PointerFormat::parse("order=pdp").is_err());
+        assert!(PointerFormat::parse("size=2").is_err());
+
+        let data = [0x34u8, 0x12, 0x00, 0x0f, 0x80, 0x10];
+        let le16 = PointerFormat::default();
+        assert_eq!(Some(0x1234), le16.read(&data, 0));
+        assert_eq!(None, le16.read(&data, 5));
+        assert_eq!(Some(0x000f8010), f.read(&data, 2));
+        let id = AddressMap::new();
+        assert_eq!(Some(0x10), f.target(0xf8010, data.len() + 0x10, &id, None));
+        assert_eq!(None, f.target(0xf8010, 0x10, &id, None));
+        assert_eq!(None, f.target(0x10, 0x100, &id, None));
+        assert_eq!(Some(0x1234), le16.target(0x1234, 0x2000, &id, None));
+        let flat = AddressMap::flat(0xc000);
+        assert_eq!(Some(0x234), le16.target(0xc234, 0x2000, &flat, None));
+        assert_eq!(None, le16.target(0x1234, 0x2000, &flat, None));
+    }
+
+    #[test]
+    fn history() {
+        let mut h = History::new();
+        assert_eq!(None, h.back(0));
+        h.visit(10);
+        h.visit(20);
+        assert_eq!(Some(20), h.back(30));
+        assert_eq!(Some(10), h.back(20));
+        assert!(!h.can_go_back());
+        assert_eq!(Some(20), h.forward(10));
+        assert_eq!(Some(30), h.forward(20));
+        assert!(!h.can_go_forward());
+        h.back(30);
+        h.visit(20);
+        assert!(!h.can_go_forward());
+        assert_eq!(Some(20), h.back(40));
+    }
+}
@@ -0,0 +1,189 @@
+//! Parsing numbers as they are written in datasheets and disassemblies.
+
+/// Parse an unsigned number. Accepts decimal, hex written as `0x1F`, `$1F`
+/// or `1Fh`, octal as `0o17`, binary as `0b101`, and a `k` (1024) or `M`
+/// (1024*1024) suffix on any of them: `32k`, `0x10k`. Underscores between
+/// digits are ignored.
+pub fn parse_number(text : &str) -> Result<u64, String> {
+    let err = || format!("'{}' is not a number", text);
+    let s : String = text.trim().chars().filter(|&c| c != '_').collect();
+    let (s, scale) = if let Some(rest) = s.strip_suffix(['k', 'K']) {
+        (rest, 1u64 << 10)
+    } else if let Some(rest) = s.strip_suffix('M') {
+        (rest, 1u64 << 20)
+    } else {
+        (s.as_str(), 1)
+    };
+    let lower = s.to_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        (hex, 16)
+    } else if let Some(oct) = lower.strip_prefix("0o") {
+        (oct, 8)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    // from_str_radix would otherwise take a leading sign
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(err());
+    }
+    let n = u64::from_str_radix(digits, radix).map_err(|_| err())?;
+    n.checked_mul(scale).ok_or_else(|| format!("'{}' is too large", text))
+}
+
+/// `parse_number` for sizes and offsets.
+pub fn parse_usize(text : &str) -> Result<usize, String> {
+    let n = parse_number(text)?;
+    if n > usize::MAX as u64 { return Err(format!("'{}' is too large", text)); }
+    Ok(n as usize)
+}
+
+/// `parse_number` for values that fit in 32 bits.
+pub fn parse_u32(text : &str) -> Result<u32, String> {
+    let n = parse_number(text)?;
+    if n > u32::MAX as u64 { return Err(format!("'{}' is too large", text)); }
+    Ok(n as u32)
+}
+
+/// `parse_number` for byte values.
+pub fn parse_u8(text : &str) -> Result<u8, String> {
+    let n = parse_number(text)?;
+    if n > u8::MAX as u64 { return Err(format!("'{}' is too large for a byte", text)); }
+    Ok(n as u8)
+}
+
+/// Evaluate an address expression: numbers as taken by `parse_number`,
+/// combined with `+`, `-`, `*`, `/` and parentheses. `.` stands for `here`,
+/// so that `.+0x100` is 256 bytes on from the current position.
+pub fn eval_expr(text : &str, here : u64) -> Result<u64, String> {
+    let mut p = ExprParser { chars : text.chars().collect(), pos : 0, here };
+    let v = p.sum()?;
+    if p.peek().is_some() {
+        return Err(format!("unexpected '{}' in '{}'", p.chars[p.pos], text.trim()));
+    }
+    if v < 0 || v > u64::MAX as i128 {
+        return Err(format!("'{}' is out of range", text.trim()));
+    }
+    Ok(v as u64)
+}
+
+struct ExprParser {
+    chars : Vec<char>,
+    pos : usize,
+    here : u64,
+}
+
+impl ExprParser {
+    /// The next character other than whitespace.
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn sum(&mut self) -> Result<i128, String> {
+        let mut v = self.product()?;
+        while let Some(op) = self.peek().filter(|&c| c == '+' || c == '-') {
+            self.pos += 1;
+            let rhs = self.product()?;
+            v = if op == '+' { v + rhs } else { v - rhs };
+        }
+        Ok(v)
+    }
+
+    fn product(&mut self) -> Result<i128, String> {
+        let mut v = self.term()?;
+        while let Some(op) = self.peek().filter(|&c| c == '*' || c == '/') {
+            self.pos += 1;
+            let rhs = self.term()?;
+            v = if op == '*' {
+                v.checked_mul(rhs).ok_or("the result is too large")?
+            } else {
+                v.checked_div(rhs).ok_or("division by zero")?
+            };
+        }
+        Ok(v)
+    }
+
+    fn term(&mut self) -> Result<i128, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let v = self.sum()?;
+                if self.peek() != Some(')') { return Err("missing ')'".to_string()); }
+                self.pos += 1;
+                Ok(v)
+            },
+            Some('.') => { self.pos += 1; Ok(self.here as i128) },
+            Some('-') => { self.pos += 1; Ok(-self.term()?) },
+            _ => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|&c| c.is_ascii_alphanumeric() || c == '_' || c == '$') {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Err(match self.peek() {
+                        Some(c) => format!("unexpected '{}'", c),
+                        None => "expected a number".to_string(),
+                    });
+                }
+                let word : String = self.chars[start..self.pos].iter().collect();
+                parse_number(&word).map(|n| n as i128)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(Ok(1234), parse_number("1234"));
+        assert_eq!(Ok(0x8000), parse_number("0x8000"));
+        assert_eq!(Ok(0xC000), parse_number("$c000"));
+        assert_eq!(Ok(0xFFFE), parse_number("FFFEh"));
+        assert_eq!(Ok(0o777), parse_number("0o777"));
+        assert_eq!(Ok(5), parse_number("0b101"));
+        assert_eq!(Ok(0xB), parse_number("0BH"));
+        assert_eq!(Ok(32 * 1024), parse_number("32k"));
+        assert_eq!(Ok(2 * 1024 * 1024), parse_number("2M"));
+        assert_eq!(Ok(0x10 * 1024), parse_number("0x10K"));
+        assert_eq!(Ok(0x1_0000), parse_number("0x1_0000"));
+        assert!(parse_number("").is_err());
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("-5").is_err());
+        assert!(parse_number("+5").is_err());
+        assert!(parse_number("12q").is_err());
+        assert!(parse_number("0o8").is_err());
+        assert!(parse_number("1m").is_err()); // M only, to keep away from hex
+        assert!(parse_number("99999999999999999999").is_err());
+        assert!(parse_u32("0x100000000").is_err());
+        assert_eq!(Ok(0xffff_ffff), parse_u32("0xffffffff"));
+        assert_eq!(Ok(0xff), parse_u8("$ff"));
+        assert!(parse_u8("256").is_err());
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(Ok(0x8000), eval_expr("0x8000", 0));
+        assert_eq!(Ok(0xC010), eval_expr(" $c000 + 10h ", 0));
+        assert_eq!(Ok(0x1100), eval_expr(".+0x100", 0x1000));
+        assert_eq!(Ok(14), eval_expr("2+3*4", 0));
+        assert_eq!(Ok(20), eval_expr("(2+3)*4", 0));
+        assert_eq!(Ok(3 * 1024), eval_expr("16k/4-1k", 0));
+        assert_eq!(Ok(4), eval_expr("-2+6", 0));
+        assert!(eval_expr("2-6", 0).is_err());
+        assert!(eval_expr("1/0", 0).is_err());
+        assert!(eval_expr("(1+2", 0).is_err());
+        assert!(eval_expr("1+", 0).is_err());
+        assert!(eval_expr("1 2", 0).is_err());
+        assert!(eval_expr("", 0).is_err());
+        assert!(eval_expr("zz", 0).is_err());
+    }
+}
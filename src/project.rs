@@ -0,0 +1,242 @@
+//! Project files: the annotations and view state for a ROM, saved beside it
+//! as `<rom>.romexp.json` so that work survives between sessions.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use annotation::{AnnotationStore, GenericAnnotation};
+use layer::{Layer, LayerStack};
+
+/// Bumped whenever the file format changes incompatibly.
+pub const FORMAT_VERSION : u32 = 1;
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    /// The file was written by an incompatible version of the explorer.
+    Version(u32),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProjectError::Io(ref e) => write!(f, "{}", e),
+            ProjectError::Format(ref e) => write!(f, "malformed project file: {}", e),
+            ProjectError::Version(v) => write!(f, "unsupported project file version {}", v),
+        }
+    }
+}
+
+impl Error for ProjectError {}
+
+impl From<io::Error> for ProjectError {
+    fn from(e : io::Error) -> Self { ProjectError::Io(e) }
+}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(e : serde_json::Error) -> Self { ProjectError::Format(e) }
+}
+
+/// Everything needed to put the view back the way it was.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewState {
+    pub word : u32,
+    pub bpp : u8,
+    pub spacing : u32,
+    pub col_height : u32,
+    pub data_offset : usize,
+    pub zoom : f32,
+    pub pan : (f32, f32),
+    /// byte order, by name; missing from older projects
+    #[serde(default)]
+    pub byte_order : String,
+    /// transform pipeline the data is shown through, as typed in
+    #[serde(default)]
+    pub transform : String,
+}
+
+impl ViewState {
+    /// Whether the saved word size, bits per pixel and column height can be
+    /// drawn; a hand edited project may hold anything.
+    pub fn layout_is_valid(&self) -> bool {
+        self.word > 0 && self.word.is_multiple_of(8) && self.col_height > 0 &&
+            matches!(self.bpp, 1 | 2 | 4 | 8)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedAnnotation {
+    pub start : usize,
+    pub end : usize,
+    #[serde(rename = "type")]
+    pub type_str : String,
+    pub confidence : u8,
+    pub comments : String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedLayer {
+    pub title : String,
+    pub color : (f32, f32, f32),
+    pub visible : bool,
+    pub annotations : Vec<SavedAnnotation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub version : u32,
+    /// CRC-32 and length of the ROM the project was made for
+    pub rom_crc32 : u32,
+    pub rom_len : usize,
+    pub view : ViewState,
+    /// first and last byte of the selection, if any
+    pub selection : Option<(usize, usize)>,
+    /// annotation layers, bottom of the z-order first
+    pub layers : Vec<SavedLayer>,
+}
+
+/// Where the project for the ROM at `rom_path` is kept.
+pub fn project_path<P : AsRef<Path>>(rom_path : P) -> PathBuf {
+    let mut name = rom_path.as_ref().as_os_str().to_os_string();
+    name.push(".romexp.json");
+    PathBuf::from(name)
+}
+
+/// Standard (IEEE 802.3) CRC-32, as used by MAME and zip to identify ROMs.
+pub fn crc32(data : &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+    let mut crc = !0u32;
+    for &b in data {
+        crc = table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+impl Project {
+    pub fn new(rom : &[u8], view : ViewState, selection : Option<(usize, usize)>,
+               layers : &LayerStack) -> Project {
+        let layers = layers.iter().map(|l| SavedLayer {
+            title : l.title().to_string(),
+            color : l.color,
+            visible : l.visible,
+            annotations : l.store.iter().map(|a| {
+                let (start, end) = a.span();
+                SavedAnnotation {
+                    start,
+                    end,
+                    type_str : a.type_str().to_string(),
+                    confidence : a.confidence(),
+                    comments : a.comments().to_string(),
+                }
+            }).collect(),
+        }).collect();
+        Project {
+            version : FORMAT_VERSION,
+            rom_crc32 : crc32(rom),
+            rom_len : rom.len(),
+            view,
+            selection,
+            layers,
+        }
+    }
+
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Project, ProjectError> {
+        let project : Project = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if project.version != FORMAT_VERSION {
+            return Err(ProjectError::Version(project.version));
+        }
+        Ok(project)
+    }
+
+    pub fn save<P : AsRef<Path>>(&self, path : P) -> Result<(), ProjectError> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Whether this project was made for the given ROM contents.
+    pub fn matches(&self, rom : &[u8]) -> bool {
+        self.rom_len == rom.len() && self.rom_crc32 == crc32(rom)
+    }
+
+    /// Rebuild the saved annotation layers.
+    pub fn layer_stack(&self) -> LayerStack {
+        let mut stack = LayerStack::new();
+        for saved in &self.layers {
+            let mut store = AnnotationStore::new(saved.title.as_str());
+            store.extend(saved.annotations.iter().map(|a| {
+                Box::new(GenericAnnotation {
+                    start : a.start,
+                    end : a.end,
+                    type_str : a.type_str.clone(),
+                    confidence : a.confidence,
+                    comments : a.comments.clone(),
+                }) as Box<_>
+            }));
+            stack.push(Layer { store, color : saved.color, visible : saved.visible });
+        }
+        stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn project_round_trip() {
+        let rom = include_bytes!("../sample_binaries/strings-test.bin");
+        let mut layers = LayerStack::new();
+        let mut store = AnnotationStore::new("User");
+        store.insert(Box::new(GenericAnnotation {
+            start : 2, end : 9, type_str : "Code".to_string(),
+            confidence : 255, comments : "reset \"vector\"".to_string() }));
+        layers.add(store);
+        layers.get_mut(0).unwrap().visible = false;
+        let view = ViewState { word : 16, bpp : 2, spacing : 4, col_height : 512,
+                               data_offset : 3, zoom : 2.5, pan : (10.0, -4.0),
+                               byte_order : "LE32".to_string(),
+                               transform : "xor=ff nibble".to_string() };
+        assert!(view.layout_is_valid());
+        for &(word, bpp, col_height) in &[(0, 2, 512), (12, 2, 512), (16, 0, 512), (16, 3, 512), (16, 2, 0)] {
+            assert!(!ViewState { word, bpp, col_height, ..view.clone() }.layout_is_valid());
+        }
+        let project = Project::new(rom, view, Some((5, 7)), &layers);
+
+        let text = serde_json::to_string(&project).unwrap();
+        let loaded : Project = serde_json::from_str(&text).unwrap();
+        assert_eq!(project, loaded);
+        assert!(loaded.matches(rom));
+        assert!(!loaded.matches(&rom[1..]));
+
+        let restored = loaded.layer_stack();
+        assert_eq!(1, restored.len());
+        let layer = restored.get(0).unwrap();
+        assert!(!layer.visible);
+        assert_eq!("User", layer.title());
+        let a = layer.store.query(4)[0];
+        assert_eq!((2, 9), a.span());
+        assert_eq!("reset \"vector\"", a.comments());
+    }
+
+    #[test]
+    fn project_path_appends_suffix() {
+        assert_eq!(PathBuf::from("roms/bios.bin.romexp.json"), project_path("roms/bios.bin"));
+    }
+}
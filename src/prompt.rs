@@ -0,0 +1,40 @@
+/// A line of text being typed into the window, such as a comment or an address.
+pub struct Prompt<A> {
+    pub label : String,
+    pub text : String,
+    /// what to do with the text once it is entered
+    pub action : A,
+    /// The key that opened the prompt also arrives as a character; drop it.
+    skip_char : bool,
+}
+
+impl<A> Prompt<A> {
+    /// Open a prompt from a key press, whose character is not part of the text.
+    pub fn from_key(label : &str, text : &str, action : A) -> Prompt<A> {
+        Prompt { label : label.to_string(), text : text.to_string(), action, skip_char : true }
+    }
+
+    /// Open a prompt from a mouse click.
+    pub fn from_click(label : &str, text : &str, action : A) -> Prompt<A> {
+        Prompt { label : label.to_string(), text : text.to_string(), action, skip_char : false }
+    }
+
+    pub fn push_char(&mut self, c : char) {
+        if self.skip_char {
+            self.skip_char = false;
+        } else if !c.is_control() && c.is_ascii() {
+            // the font only covers ASCII
+            self.text.push(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.skip_char = false;
+        self.text.pop();
+    }
+
+    /// The line as drawn, with a cursor at the end.
+    pub fn display(&self) -> String {
+        format!("{}: {}_", self.label, self.text)
+    }
+}
@@ -0,0 +1,97 @@
+use std::io;
+use std::io::Write;
+
+use addrmap::AddressMap;
+use annotation::AnnotationStore;
+
+/// Output formats supported by the headless `annotate` command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name : &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Write every annotation in the given stores to `out` in the requested format.
+/// Given an address map, each span's addresses are included beside its offsets.
+pub fn write_report<W : Write>(out : &mut W, stores : &[AnnotationStore], format : Format,
+                               map : Option<&AddressMap>) -> io::Result<()> {
+    match format {
+        Format::Text => write_text(out, stores, map),
+        Format::Csv => write_csv(out, stores, map),
+        Format::Json => write_json(out, stores, map),
+    }
+}
+
+fn write_text<W : Write>(out : &mut W, stores : &[AnnotationStore], map : Option<&AddressMap>) -> io::Result<()> {
+    for store in stores {
+        writeln!(out, "# {}", store.title())?;
+        for a in store.iter() {
+            let (start, end) = a.span();
+            write!(out, "0x{:08x}-0x{:08x}  ", start, end)?;
+            if let Some(map) = map {
+                write!(out, "{:>10}-{:<10}  ", map.format(start), map.format(end))?;
+            }
+            writeln!(out, "{:<16} {:>3}  {}", a.type_str(), a.confidence(), a.comments())?;
+        }
+    }
+    Ok(())
+}
+
+fn csv_field(s : &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_csv<W : Write>(out : &mut W, stores : &[AnnotationStore], map : Option<&AddressMap>) -> io::Result<()> {
+    writeln!(out, "store,start,end,{}type,confidence,comments",
+             if map.is_some() { "start_address,end_address," } else { "" })?;
+    for store in stores {
+        for a in store.iter() {
+            let (start, end) = a.span();
+            write!(out, "{},{},{},", csv_field(store.title()), start, end)?;
+            if let Some(map) = map {
+                write!(out, "{},{},", map.format(start), map.format(end))?;
+            }
+            writeln!(out, "{},{},{}", csv_field(a.type_str()), a.confidence(), csv_field(a.comments()))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json<W : Write>(out : &mut W, stores : &[AnnotationStore], map : Option<&AddressMap>) -> io::Result<()> {
+    let doc : Vec<serde_json::Value> = stores.iter().map(|store| {
+        let annotations : Vec<serde_json::Value> = store.iter().map(|a| {
+            let (start, end) = a.span();
+            let mut v = json!({
+                "start" : start,
+                "end" : end,
+                "type" : a.type_str(),
+                "confidence" : a.confidence(),
+                "comments" : a.comments(),
+            });
+            if let Some(map) = map {
+                v["start_address"] = json!(map.format(start));
+                v["end_address"] = json!(map.format(end));
+            }
+            v
+        }).collect();
+        json!({ "title" : store.title(), "annotations" : annotations })
+    }).collect();
+    serde_json::to_writer_pretty(&mut *out, &doc)?;
+    writeln!(out)
+}
@@ -0,0 +1,159 @@
+//! Searching the image for byte sequences, text and regular expressions.
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use annotation::{Annotation, AnnotationStore, GenericAnnotation};
+
+/// Title of the layer search results go into.
+pub const SEARCH_LAYER : &str = "Search results";
+
+/// Most matches kept from one search.
+pub const MAX_MATCHES : usize = 100_000;
+
+pub enum Pattern {
+    /// bytes to match exactly, or None for a wildcard
+    Bytes(Vec<Option<u8>>),
+    Regex(Regex),
+}
+
+fn hex_digit(c : char) -> Option<u8> {
+    c.to_digit(16).map(|d| d as u8)
+}
+
+/// Hex bytes with `??` wildcards, such as `4e 71 ?? ?? 4e 75`.
+fn parse_hex(text : &str) -> Option<Vec<Option<u8>>> {
+    let digits : Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) { return None; }
+    digits.chunks(2).map(|pair| match (pair[0], pair[1]) {
+        ('?', '?') => Some(None),
+        (hi, lo) => Some(Some(hex_digit(hi)? << 4 | hex_digit(lo)?)),
+    }).collect()
+}
+
+/// Text with C style escapes: `\n`, `\t`, `\0`, `\\`, `\"` and `\xNN`.
+fn parse_text(text : &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('0') => out.push(0),
+            Some('x') => {
+                let byte = chars.next().and_then(hex_digit)
+                    .and_then(|hi| chars.next().and_then(hex_digit).map(|lo| hi << 4 | lo));
+                out.push(byte.ok_or("\\x needs two hex digits")?);
+            },
+            Some(c) => { let mut buf = [0u8; 4]; out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()); },
+            None => return Err("trailing \\".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+impl Pattern {
+    /// Parse a search: `/regex/` for a regular expression over bytes,
+    /// `"text"` for text, and otherwise hex bytes with `??` wildcards. Anything
+    /// that isn't hex is searched for as text.
+    pub fn parse(text : &str) -> Result<Pattern, String> {
+        let t = text.trim();
+        if t.len() >= 2 && t.starts_with('/') && t.ends_with('/') {
+            // Without Unicode, \xff and . match single bytes.
+            return RegexBuilder::new(&t[1..t.len() - 1]).unicode(false).build()
+                .map(Pattern::Regex).map_err(|e| e.to_string());
+        }
+        let bytes = if t.len() >= 2 && t.starts_with('"') && t.ends_with('"') {
+            parse_text(&t[1..t.len() - 1])?
+        } else if let Some(hex) = parse_hex(t) {
+            return Ok(Pattern::Bytes(hex));
+        } else {
+            parse_text(t)?
+        };
+        if bytes.is_empty() { return Err("nothing to search for".to_string()); }
+        Ok(Pattern::Bytes(bytes.into_iter().map(Some).collect()))
+    }
+
+    fn matches_at(pat : &[Option<u8>], data : &[u8]) -> bool {
+        pat.iter().zip(data).all(|(p, &d)| p.is_none_or(|b| b == d))
+    }
+
+    /// The spans of non-overlapping matches, in order, up to `limit` of them.
+    pub fn find_all(&self, data : &[u8], limit : usize) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        match *self {
+            Pattern::Bytes(ref pat) => {
+                let mut idx = 0;
+                while idx + pat.len() <= data.len() && found.len() < limit {
+                    if Pattern::matches_at(pat, &data[idx..]) {
+                        found.push((idx, idx + pat.len()));
+                        idx += pat.len();
+                    } else {
+                        idx += 1;
+                    }
+                }
+            },
+            Pattern::Regex(ref re) => {
+                found.extend(re.find_iter(data).filter(|m| m.end() > m.start())
+                             .map(|m| (m.start(), m.end())).take(limit));
+            },
+        }
+        found
+    }
+}
+
+/// Search the data, returning the matches as a layer.
+pub fn search(data : &[u8], text : &str) -> Result<AnnotationStore, String> {
+    let pattern = Pattern::parse(text)?;
+    let found = pattern.find_all(data, MAX_MATCHES);
+    let mut store = AnnotationStore::new(SEARCH_LAYER);
+    let total = found.len();
+    let more = if total == MAX_MATCHES { "+" } else { "" };
+    store.extend(found.into_iter().enumerate().map(|(i, (start, end))| {
+        Box::new(GenericAnnotation {
+            start, end,
+            type_str : "Match".to_string(),
+            confidence : 255,
+            comments : format!("{} (match {} of {}{})", text.trim(), i + 1, total, more),
+        }) as Box<dyn Annotation>
+    }));
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(pattern : &str, data : &[u8]) -> Vec<(usize, usize)> {
+        Pattern::parse(pattern).unwrap().find_all(data, MAX_MATCHES)
+    }
+
+    #[test]
+    fn search_patterns() {
+        let data = b"\x4e\x71\x12\x34\x4e\x75 Hello\x00hello\xff\xfe";
+        assert_eq!(vec![(0, 6)], spans("4e 71 ?? ?? 4e 75", data));
+        assert_eq!(vec![(0, 1), (4, 5)], spans("4E", data));
+        assert_eq!(vec![(7, 12)], spans("Hello", data));
+        assert_eq!(vec![(7, 13)], spans("\"Hello\\0\"", data));
+        assert_eq!(vec![(7, 12), (13, 18)], spans("/[Hh]ello/", data));
+        assert_eq!(vec![(18, 20)], spans("/\\xff./", data));
+        // "beef" is hex; quoted, it is text
+        assert!(spans("beef", data).is_empty());
+        assert_eq!(vec![(1, 5)], spans("\"beef\"", b"abeefa"));
+        // matches don't overlap
+        assert_eq!(vec![(0, 2), (2, 4)], spans("\"aa\"", b"aaaaa"));
+        assert_eq!(2, Pattern::parse("??").unwrap().find_all(data, 2).len());
+        assert!(Pattern::parse("/(/").is_err());
+        assert!(Pattern::parse("\"\"").is_err());
+
+        let store = search(data, "/[Hh]ello/").unwrap();
+        assert_eq!(SEARCH_LAYER, store.title());
+        assert_eq!(2, store.len());
+        assert_eq!("/[Hh]ello/ (match 2 of 2)", store.query(14)[0].comments());
+    }
+}
@@ -0,0 +1,80 @@
+//! The selected range of bytes.
+
+/// An inclusive range of bytes, `start..=end`, with `start <= end`. Bytes
+/// are positions in the data as stored, whatever the view's layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection {
+    pub start : usize,
+    pub end : usize,
+}
+
+fn offset(idx : usize, delta : isize, limit : usize) -> usize {
+    let moved = if delta < 0 { idx.saturating_sub(delta.unsigned_abs()) } else { idx.saturating_add(delta as usize) };
+    moved.min(limit.saturating_sub(1))
+}
+
+impl Selection {
+    /// The bytes between two ends, given in either order.
+    pub fn new(a : usize, b : usize) -> Selection {
+        Selection { start : a.min(b), end : a.max(b) }
+    }
+
+    /// Every byte of data `len` bytes long.
+    pub fn all(len : usize) -> Selection {
+        Selection { start : 0, end : len.saturating_sub(1) }
+    }
+
+    pub fn len(&self) -> usize { self.end - self.start + 1 }
+
+    /// A selection always holds at least one byte.
+    pub fn is_empty(&self) -> bool { false }
+
+    pub fn contains(&self, idx : usize) -> bool { self.start <= idx && idx <= self.end }
+
+    /// Move the start by `delta` bytes, staying within `0..limit`. The end is
+    /// pushed along if the start passes it.
+    pub fn move_start(&self, delta : isize, limit : usize) -> Selection {
+        let start = offset(self.start, delta, limit);
+        Selection { start, end : self.end.max(start) }
+    }
+
+    /// Move the end by `delta` bytes, but not before the start.
+    pub fn move_end(&self, delta : isize, limit : usize) -> Selection {
+        let end = offset(self.end, delta, limit);
+        Selection { start : self.start, end : end.max(self.start) }
+    }
+
+    /// Widen the selection to whole units of `unit` bytes, counted from
+    /// `origin`, without going past `limit`.
+    pub fn snap(&self, unit : usize, origin : usize, limit : usize) -> Selection {
+        if unit == 0 || self.start < origin { return *self; }
+        let start = self.start - (self.start - origin) % unit;
+        let end = self.end + unit - 1 - (self.end - origin) % unit;
+        Selection { start, end : end.min(limit.saturating_sub(1)).max(start) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_editing() {
+        let s = Selection::new(20, 10);
+        assert_eq!(Selection { start : 10, end : 20 }, s);
+        assert_eq!(11, s.len());
+        assert!(s.contains(10) && s.contains(20) && !s.contains(21));
+
+        assert_eq!(Selection { start : 12, end : 20 }, s.move_start(2, 100));
+        assert_eq!(Selection { start : 25, end : 25 }, s.move_start(15, 100));
+        assert_eq!(Selection { start : 0, end : 20 }, s.move_start(-15, 100));
+        assert_eq!(Selection { start : 10, end : 10 }, s.move_end(-15, 100));
+        assert_eq!(Selection { start : 10, end : 99 }, s.move_end(500, 100));
+
+        // words of 4 bytes, and columns of 64 bytes starting at offset 3
+        assert_eq!(Selection { start : 8, end : 23 }, s.snap(4, 0, 100));
+        assert_eq!(Selection { start : 3, end : 66 }, s.snap(64, 3, 100));
+        assert_eq!(Selection { start : 3, end : 49 }, s.snap(64, 3, 50));
+        assert_eq!(Selection { start : 0, end : 49 }, Selection::all(50));
+    }
+}
@@ -0,0 +1,234 @@
+//! Undoing the scrambling of dumps whose address or data lines were wired
+//! up out of order, or which were encrypted or interleaved.
+
+use std::fmt;
+
+use parse;
+
+/// One step of a transform pipeline.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transform {
+    /// Output data bit `7 - j` is input bit `perm[j]`.
+    Bits([u8; 8]),
+    /// Within each block of `2^perm.len()` bytes, output address line
+    /// `n - 1 - j` is input address line `perm[j]`.
+    Address(Vec<u8>),
+    /// XOR with the key, repeated over the data.
+    Xor(Vec<u8>),
+    /// Swap the high and low nibble of each byte.
+    NibbleSwap,
+    /// Gather every `ways`th unit of `unit` bytes, so that N-way
+    /// interleaved data is shown as one chip after another.
+    Deinterleave { ways : usize, unit : usize },
+}
+
+/// Check that `perm` holds each of `0..perm.len()` exactly once.
+fn check_permutation(perm : &[u8], what : &str) -> Result<(), String> {
+    let mut seen = vec![false; perm.len()];
+    for &p in perm {
+        match seen.get_mut(p as usize) {
+            Some(s) if !*s => *s = true,
+            _ => return Err(format!("{} {:?} must use each line from 0 to {} once", what, perm, perm.len() - 1)),
+        }
+    }
+    Ok(())
+}
+
+impl Transform {
+    /// One step: `bits=76543201`, `addr=14,13,...,0`, `xor=a5`, `nibble` or
+    /// `deinterleave=2` (or `deinterleave=2x2`, for units of two bytes).
+    pub fn parse(text : &str) -> Result<Transform, String> {
+        let (name, value) = match text.find('=') {
+            Some(i) => (&text[..i], &text[i + 1..]),
+            None => (text, ""),
+        };
+        match name {
+            "bits" => {
+                let digits : Vec<u8> = value.bytes().map(|c| c.wrapping_sub(b'0')).collect();
+                if digits.len() != 8 {
+                    return Err(format!("bits={} should give the source of each of the 8 bits, from 7 down to 0", value));
+                }
+                check_permutation(&digits, "bits")?;
+                let mut perm = [0; 8];
+                perm.copy_from_slice(&digits);
+                Ok(Transform::Bits(perm))
+            },
+            "addr" => {
+                let perm = value.split(',').map(|s| s.trim().parse::<u8>())
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| format!("addr={} should list address lines, high first, like 1,0", value))?;
+                if perm.len() > 32 {
+                    return Err(format!("addr={} has too many address lines", value));
+                }
+                check_permutation(&perm, "addr")?;
+                Ok(Transform::Address(perm))
+            },
+            "xor" => {
+                let digits = value.trim_start_matches("0x");
+                if digits.is_empty() || digits.len() % 2 != 0 {
+                    return Err(format!("xor={} should be a key of whole hex bytes", value));
+                }
+                let key = (0..digits.len()).step_by(2)
+                    .map(|i| digits.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| format!("xor={} should be a key of whole hex bytes", value))?;
+                Ok(Transform::Xor(key))
+            },
+            "nibble" if value.is_empty() => Ok(Transform::NibbleSwap),
+            "deinterleave" => {
+                // The x between the two numbers, not one in a 0x prefix.
+                let b = value.as_bytes();
+                let sep = (1..b.len()).find(|&i| b[i] == b'x' && !(b[i - 1] == b'0' && (i == 1 || b[i - 2] == b'x')));
+                let (ways, unit) = match sep {
+                    Some(i) => (&value[..i], &value[i + 1..]),
+                    None => (value, "1"),
+                };
+                let ways = parse::parse_usize(ways)?;
+                let unit = parse::parse_usize(unit)?;
+                if ways < 2 || unit == 0 {
+                    return Err(format!("deinterleave={} needs at least 2 ways of at least 1 byte", value));
+                }
+                if ways.checked_mul(unit).is_none() {
+                    return Err(format!("deinterleave={} makes blocks too large", value));
+                }
+                Ok(Transform::Deinterleave { ways, unit })
+            },
+            _ => Err(format!("unknown transform {}; use bits=, addr=, xor=, nibble or deinterleave=", text)),
+        }
+    }
+
+    /// Apply the step to `data`. Bytes left over past the last whole block
+    /// are passed through as they are.
+    pub fn apply(&self, data : &[u8]) -> Vec<u8> {
+        match *self {
+            Transform::Bits(ref perm) => {
+                let mut table = [0u8; 256];
+                for (b, out) in table.iter_mut().enumerate() {
+                    for (j, &src) in perm.iter().enumerate() {
+                        if b & (1 << src) != 0 { *out |= 1 << (7 - j); }
+                    }
+                }
+                data.iter().map(|&b| table[b as usize]).collect()
+            },
+            Transform::Address(ref perm) => {
+                let n = perm.len();
+                let block = 1usize << n;
+                let src = |a : usize| perm.iter().enumerate()
+                    .fold(0, |s, (j, &p)| s | (((a >> (n - 1 - j)) & 1) << p));
+                let whole = data.len() - data.len() % block;
+                let mut out = Vec::with_capacity(data.len());
+                for base in (0..whole).step_by(block) {
+                    out.extend((0..block).map(|a| data[base + src(a)]));
+                }
+                out.extend_from_slice(&data[whole..]);
+                out
+            },
+            Transform::Xor(ref key) => data.iter().zip(key.iter().cycle()).map(|(&b, &k)| b ^ k).collect(),
+            Transform::NibbleSwap => data.iter().map(|&b| b.rotate_left(4)).collect(),
+            Transform::Deinterleave { ways, unit } => {
+                let group = ways * unit;
+                let whole = data.len() - data.len() % group;
+                let mut out = Vec::with_capacity(data.len());
+                for way in 0..ways {
+                    for base in (way * unit..whole).step_by(group) {
+                        out.extend_from_slice(&data[base..base + unit]);
+                    }
+                }
+                out.extend_from_slice(&data[whole..]);
+                out
+            },
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Transform::Bits(ref perm) => {
+                write!(f, "bits=")?;
+                perm.iter().try_for_each(|p| write!(f, "{}", p))
+            },
+            Transform::Address(ref perm) => {
+                let lines : Vec<String> = perm.iter().map(|p| p.to_string()).collect();
+                write!(f, "addr={}", lines.join(","))
+            },
+            Transform::Xor(ref key) => {
+                write!(f, "xor=")?;
+                key.iter().try_for_each(|k| write!(f, "{:02x}", k))
+            },
+            Transform::NibbleSwap => write!(f, "nibble"),
+            Transform::Deinterleave { ways, unit : 1 } => write!(f, "deinterleave={}", ways),
+            Transform::Deinterleave { ways, unit } => write!(f, "deinterleave={}x{}", ways, unit),
+        }
+    }
+}
+
+/// Transforms applied one after another, as given on the command line or
+/// typed into the viewer: steps separated by spaces.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pipeline {
+    pub steps : Vec<Transform>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline { Pipeline::default() }
+
+    pub fn parse(text : &str) -> Result<Pipeline, String> {
+        let steps = text.split_whitespace().map(Transform::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(Pipeline { steps })
+    }
+
+    pub fn is_empty(&self) -> bool { self.steps.is_empty() }
+
+    pub fn apply(&self, data : &[u8]) -> Vec<u8> {
+        let mut out = data.to_vec();
+        for step in &self.steps {
+            out = step.apply(&out);
+        }
+        out
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let steps : Vec<String> = self.steps.iter().map(|s| s.to_string()).collect();
+        write!(f, "{}", steps.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms() {
+        let t = |text : &str, data : &[u8]| Pipeline::parse(text).unwrap().apply(data);
+        // swap D0 and D1
+        assert_eq!(vec![0x02, 0x01, 0x83], t("bits=76543201", &[0x01, 0x02, 0x83]));
+        // reverse the bits
+        assert_eq!(vec![0x80, 0x0f], t("bits=01234567", &[0x01, 0xf0]));
+        // swap A0 and A1, leaving the last, partial block alone
+        assert_eq!(vec![0, 2, 1, 3, 4, 6, 5, 7, 8], t("addr=0,1", &[0, 1, 2, 3, 4, 5, 6, 7, 8]));
+        // rotate the address lines: output A2..A0 from input A1, A0, A2
+        assert_eq!(vec![0, 4, 1, 5, 2, 6, 3, 7], t("addr=1,0,2", &[0, 1, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(vec![0xa4, 0x5b, 0xa5], t("xor=a55a", &[0x01, 0x01, 0x00]));
+        assert_eq!(vec![0x21, 0xf0], t("nibble", &[0x12, 0x0f]));
+        assert_eq!(vec![0, 2, 4, 1, 3, 5, 6], t("deinterleave=2", &[0, 1, 2, 3, 4, 5, 6]));
+        assert_eq!(vec![0, 1, 4, 5, 2, 3, 6, 7], t("deinterleave=2x2", &[0, 1, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(vec![0x0e, 0xfe], t("xor=ff nibble", &[0x1f, 0x10]));
+
+        let p = Pipeline::parse("bits=76543201 addr=0,1 xor=a5 nibble deinterleave=4x2").unwrap();
+        assert_eq!(p, Pipeline::parse(&p.to_string()).unwrap());
+        assert!(Pipeline::parse("").unwrap().is_empty());
+        assert!(Pipeline::parse("bits=76543211").is_err());
+        assert!(Pipeline::parse("bits=7654321").is_err());
+        assert!(Pipeline::parse("addr=0,2").is_err());
+        assert!(Pipeline::parse("xor=abc").is_err());
+        assert!(Pipeline::parse("deinterleave=1").is_err());
+        assert_eq!(Ok(Transform::Deinterleave { ways : 4, unit : 1 }), Transform::parse("deinterleave=0x4"));
+        assert_eq!(Ok(Transform::Deinterleave { ways : 2, unit : 16 }), Transform::parse("deinterleave=0x2x0x10"));
+        assert_eq!(Ok(Transform::Deinterleave { ways : 10, unit : 2 }), Transform::parse("deinterleave=10x2"));
+        assert!(Pipeline::parse("deinterleave=0x100000000x0x100000000").is_err());
+        assert!(Pipeline::parse("swizzle").is_err());
+    }
+}
@@ -5,16 +5,39 @@ use gl;
 use gl::types::*;
 
 use std;
+use std::borrow::Cow;
 
-use annotation;
+use romexp::addrmap::AddressMap;
+use romexp::annotation;
+use romexp::annotation::user;
+use romexp::charset::{Charset, CHARSET_NAMES};
+use romexp::layer::{LayerStack, MAX_LAYERS};
+use romexp::layout::{ByteOrder, Layout, MAX_GROUP};
+use romexp::nav::{History, PointerFormat};
+use romexp::export;
+use romexp::image::{RomImage, FILES_LAYER};
+use romexp::inspect;
+use romexp::parse;
+use romexp::search;
+use romexp::selection::Selection;
+use romexp::transform::Pipeline;
+use romexp::project::{Project, ViewState};
 use glutil;
 use font;
+use prompt::Prompt;
 
 // Shader sources
 static VS_SRC: &'static str = include_str!("vs.glsl");
 
 static FS_SRC: &'static str = include_str!("fs.glsl");
 
+// Bytes on each line of the dump panel
+const DUMP_WIDTH : usize = 8;
+// Width of the dump panel, in characters
+const DUMP_CHARS : usize = 50;
+// Width of the inspector's name column, in characters
+const INSPECT_NAME : usize = 8;
+
 // Dragging is a stateful mouse interaction.
 enum MouseDragOp {
     NoOp,
@@ -23,6 +46,41 @@ enum MouseDragOp {
 }
 
 
+// How each pixel is coloured.
+#[derive(Clone, Copy, PartialEq)]
+enum ViewMode {
+    /// by the value of its bits
+    Raw = 0,
+    /// by the entropy of the block its byte lies in
+    Entropy = 1,
+}
+
+// What to do with the text typed into the prompt.
+enum PromptAction {
+    /// Make a user annotation covering the span
+    Annotate { span : (usize, usize) },
+    /// Replace (or, given no text, delete) the user annotation with this span
+    EditAnnotation { span : (usize, usize) },
+    /// Run the engine with the typed parameters
+    RunEngine { id : &'static str },
+    /// Set the custom byte order
+    CustomByteOrder,
+    /// Set how pointers are read
+    PointerFormat,
+    /// Centre the view on the address
+    GoTo,
+    /// Move one end of the selection, or set its length
+    SelectStart,
+    SelectEnd,
+    SelectLength,
+    /// Write the selection to the named file, optionally in display byte order
+    Export { selection : Selection, order : Option<ByteOrder> },
+    /// Search for the pattern
+    Search,
+    /// Replace the transform pipeline
+    Transform,
+}
+
 pub struct MouseState {
     last_pos : (f64, f64),
     moved : bool, //< Whether we've actually dragged or just clicked
@@ -44,28 +102,53 @@ pub struct Visualizer<'a> {
     pub events : std::sync::mpsc::Receiver<(f64, WindowEvent)>,
     program : GLuint,
     vao : GLuint,
-    data_len : usize,
-    data_offset : usize,
-    bpp : u8,
-    /// width, in bits, of each column
-    word : u32,
-    swap_endian : bool,
-    /// height, in rows, of each colum
-    col_height : u32,
-    /// spacing, in pixels, between columns
-    spacing : u32,
-    /// start and end of current selection, as byte idx
-    selection : (u32, u32),
+    layout : Layout,
+    /// byte order that cycling reaches after LE64, if one has been set
+    custom_order : Option<ByteOrder>,
+    selection : Option<Selection>,
     texture : GLuint,
     annotation_tex : GLuint,
     annotation_d : Vec<u8>,
+    view_mode : ViewMode,
+    /// per-block entropy, built the first time the heat map is shown
+    entropy_tex : Option<GLuint>,
+    /// size, in bytes, of the blocks in the entropy heat map
+    entropy_block : usize,
     zoom : f32,
     ul_offset : (f32, f32), // offset of upper left hand corner IN PX OF CURRENT ZOOM
     pub closed : bool,
     mouse_state : MouseState,
-    dat : &'a [u8],
-    annotation_store : Option<annotation::AnnotationStore>,
+    /// the data as loaded, before any transform
+    raw : &'a [u8],
+    /// the data as shown
+    dat : Cow<'a, [u8]>,
+    transform : Pipeline,
+    layers : LayerStack,
+    /// index of the layer that layer commands apply to
+    active_layer : usize,
     font : font::Font,
+    prompt : Option<Prompt<PromptAction>>,
+    /// byte picked out by the last go to
+    highlight : Option<usize>,
+    /// the current search match and the number of matches
+    search_match : Option<(usize, usize)>,
+    show_dump : bool,
+    /// first position shown in the dump, once it has been scrolled away from the cursor
+    dump_top : Option<usize>,
+    /// byte last under the mouse, which the dump follows when nothing is selected
+    dump_focus : usize,
+    /// whether the inspector is shown below the dump
+    show_inspector : bool,
+    /// charset the inspector decodes characters with
+    charset : Charset,
+    /// how pointers are read when following them
+    pointer : PointerFormat,
+    /// places left by following pointers
+    history : History,
+    /// where the image sits in the CPU's address space
+    map : AddressMap,
+    /// the files the data came from, when it was merged from several
+    image : Option<&'a RomImage>,
 }
 
 
@@ -91,6 +174,7 @@ impl<'a> Visualizer<'a> {
         gl::load_with(|s| window.get_proc_address(s) as *const _);
         let program = glutil::build_program(VS_SRC, FS_SRC).unwrap();
         window.set_key_polling(true);
+        window.set_char_polling(true);
         window.set_cursor_pos_polling(true);
         window.set_mouse_button_polling(true);
         window.set_scroll_polling(true);
@@ -164,47 +248,185 @@ impl<'a> Visualizer<'a> {
 
         }
 
-        
+        let font = font::Font::new();
+        // Widen the window to make room for the dump panel
+        window.set_size(size.0 as i32 + font.width(&" ".repeat(DUMP_CHARS)), size.1 as i32);
+
         Visualizer {
             window : window,
             events : events,
             program : program,
             vao : vao,
-            data_len : dat.len(),
-	    data_offset : 0,
-	    bpp : 1,
-            word : 8,
-            swap_endian : false,
-            col_height : 512,
-            spacing : 4,
-            selection : (0,0),
+            layout : Layout::new(dat.len()),
+            custom_order : None,
+            selection : None,
             texture : texture,
             annotation_tex : annotation_tex,
             annotation_d : annotation_d,
+            view_mode : ViewMode::Raw,
+            entropy_tex : None,
+            entropy_block : 256,
             zoom : 1.0,
             ul_offset : (0.0, 0.0),
             closed: false,
             mouse_state : MouseState::new(),
-            dat : dat,
-            annotation_store : None,
-            font : font::Font::new(),
+            raw : dat,
+            dat : Cow::Borrowed(dat),
+            transform : Pipeline::new(),
+            layers : LayerStack::new(),
+            active_layer : 0,
+            font,
+            prompt : None,
+            highlight : None,
+            search_match : None,
+            show_dump : true,
+            dump_top : None,
+            dump_focus : 0,
+            show_inspector : true,
+            charset : Charset::ascii(),
+            pointer : PointerFormat::default(),
+            history : History::new(),
+            map : AddressMap::new(),
+            image : None,
         }
     }
 
     pub fn set_offset(&mut self, offset : usize) {
-	self.data_offset = offset;
+	self.layout.data_offset = offset;
     }
     
-    pub fn set_selection(&mut self, start : u32, finish : u32) {
-        self.selection = (start, finish);
+    pub fn set_selection(&mut self, selection : Option<Selection>) {
+        self.selection = selection;
+        self.search_match = None;
+        self.dump_top = None;
     }
 
     pub fn set_word(&mut self, word : u32) {
-        self.word = word;
+        self.layout.word = word;
+    }
+
+    pub fn set_byte_order(&mut self, order : ByteOrder) {
+        if let ByteOrder::Custom { .. } = order {
+            self.custom_order = Some(order);
+        }
+        self.layout.byte_order = order;
+    }
+
+    pub fn set_bpp(&mut self, bpp : u8) {
+        self.layout.bpp = bpp;
+    }
+
+    pub fn set_height(&mut self, height : u32) {
+        self.layout.col_height = height;
+    }
+
+    /// Show only the data before `end`.
+    pub fn set_end(&mut self, end : usize) {
+        self.layout.data_len = end.min(self.dat.len());
+    }
+
+    pub fn set_zoom(&mut self, zoom : f32) {
+        self.zoom = zoom;
+    }
+
+    pub fn set_charset(&mut self, charset : Charset) {
+        self.charset = charset;
+    }
+
+    pub fn set_pointer_format(&mut self, pointer : PointerFormat) {
+        self.pointer = pointer;
+    }
+
+    /// Show which file each byte came from, if the image was merged from
+    /// several or read from a hex file with gaps; call after restoring a project.
+    pub fn set_image(&mut self, image : &'a RomImage) {
+        if image.sources().len() > 1 {
+            self.image = Some(image);
+        }
+        if image.is_pieced() && self.layers.find(FILES_LAYER).is_none() {
+            self.add_layer(image.files_layer());
+        }
+    }
+
+    /// Show the data through a pipeline of transforms, replacing any before.
+    pub fn set_transform(&mut self, transform : Pipeline) {
+        self.dat = if transform.is_empty() { Cow::Borrowed(self.raw) } else { Cow::Owned(transform.apply(self.raw)) };
+        self.transform = transform;
+        let maxw : usize = 16384;
+        let th : usize = self.dat.len().div_ceil(maxw);
+        let mut d = self.dat.to_vec();
+        d.resize(maxw*th, 0);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, maxw as GLsizei, th as GLsizei,
+                              gl::RED_INTEGER, gl::UNSIGNED_BYTE, d.as_ptr() as *const GLvoid);
+        }
+        // The heat map is of the old data; rebuild it if it's showing.
+        if let Some(tex) = self.entropy_tex.take() {
+            unsafe { gl::DeleteTextures(1, &tex); }
+            if self.view_mode == ViewMode::Entropy {
+                self.build_entropy_texture();
+            }
+        }
+    }
+
+    pub fn set_address_map(&mut self, map : AddressMap) {
+        self.map = map;
     }
 
     pub fn set_spacing(&mut self, spacing : u32) {
-        self.spacing = spacing;
+        self.layout.spacing = spacing;
+    }
+
+    /// Snapshot the annotations and view for saving.
+    pub fn project(&self) -> Project {
+        let view = ViewState {
+            word : self.layout.word,
+            bpp : self.layout.bpp,
+            spacing : self.layout.spacing,
+            col_height : self.layout.col_height,
+            data_offset : self.layout.data_offset,
+            zoom : self.zoom,
+            pan : self.ul_offset,
+            byte_order : self.layout.byte_order.name(),
+            transform : self.transform.to_string(),
+        };
+        Project::new(self.raw, view, self.selection.map(|s| (s.start, s.end)), &self.layers)
+    }
+
+    /// Put back the annotations and view saved in a project.
+    pub fn restore(&mut self, project : &Project) {
+        let view = &project.view;
+        if view.layout_is_valid() {
+            self.layout.word = view.word;
+            self.layout.bpp = view.bpp;
+            self.layout.spacing = view.spacing;
+            self.layout.col_height = view.col_height;
+        } else {
+            println!("Ignoring the saved layout: word {} bits, {} bpp, height {}",
+                     view.word, view.bpp, view.col_height);
+        }
+        self.layout.data_offset = view.data_offset;
+        if view.zoom > 0.0 && view.zoom.is_finite() {
+            self.zoom = view.zoom;
+        }
+        self.ul_offset = view.pan;
+        // Older projects saved no byte order; keep the current one for them.
+        if !view.byte_order.is_empty() {
+            match ByteOrder::from_name(&view.byte_order) {
+                Ok(order) => self.set_byte_order(order),
+                Err(e) => println!("Ignoring the saved byte order: {}", e),
+            }
+        }
+        match Pipeline::parse(&view.transform) {
+            Ok(transform) => self.set_transform(transform),
+            Err(e) => println!("Ignoring the saved transform: {}", e),
+        }
+        self.set_selection(project.selection.map(|(start, end)| Selection::new(start, end)));
+        self.layers = project.layer_stack();
+        self.active_layer = 0;
+        self.update_annotations();
     }
 
     pub fn uniloc(&self, name : &str) -> GLint {
@@ -226,55 +448,113 @@ impl<'a> Visualizer<'a> {
             gl::BindTexture(gl::TEXTURE_2D, self.annotation_tex);
             
             gl::Uniform4ui(self.uniloc("win"),0,0,size.0 as u32,size.1 as u32);
-            gl::Uniform1ui(self.uniloc("colwidth"), self.word / self.bpp as u32);
-            gl::Uniform1ui(self.uniloc("colheight"), self.col_height);
-            //gl::Uniform1ui(self.uniloc("swap_endian"), if self.swap_endian { 1 } else { 0 } as u32);
-            gl::Uniform1ui(self.uniloc("colspace"), self.spacing);
-            gl::Uniform1ui(self.uniloc("datalen"), self.data_len as u32);
-            gl::Uniform1ui(self.uniloc("dataoff"), self.data_offset as u32);
-            gl::Uniform1ui(self.uniloc("bpp"), self.bpp as u32);
-            gl::Uniform2ui(self.uniloc("selection"), self.selection.0, self.selection.1);
+            gl::Uniform1ui(self.uniloc("panel_width"), self.dump_width() as u32);
+            gl::Uniform1ui(self.uniloc("colwidth"), self.layout.col_width());
+            gl::Uniform1ui(self.uniloc("colheight"), self.layout.col_height);
+            let perm = self.layout.byte_order.permutation();
+            let mut byte_perm = [0 as GLuint; MAX_GROUP];
+            for (dst, &src) in byte_perm.iter_mut().zip(perm.iter()) { *dst = src as GLuint; }
+            gl::Uniform1uiv(self.uniloc("byte_perm"), MAX_GROUP as GLsizei, byte_perm.as_ptr());
+            gl::Uniform1ui(self.uniloc("perm_len"), perm.len() as u32);
+            gl::Uniform1ui(self.uniloc("colspace"), self.layout.spacing);
+            gl::Uniform1ui(self.uniloc("datalen"), self.layout.data_len as u32);
+            gl::Uniform1ui(self.uniloc("dataoff"), self.layout.data_offset as u32);
+            gl::Uniform1ui(self.uniloc("bpp"), self.layout.bpp as u32);
+            gl::Uniform1ui(self.uniloc("highlight"), self.highlight.unwrap_or(0) as u32);
+            gl::Uniform1ui(self.uniloc("highlight_on"), self.highlight.is_some() as u32);
+            let sel = self.selection.unwrap_or(Selection::new(0, 0));
+            gl::Uniform2ui(self.uniloc("selection"), sel.start as u32, sel.end as u32);
+            gl::Uniform1ui(self.uniloc("selection_on"), self.selection.is_some() as u32);
             gl::Uniform1ui(self.uniloc("texwidth"), 16384 as u32);
             gl::Uniform1i(self.uniloc("romtex"), 0 as i32); //self.texture as i32);
             gl::Uniform1i(self.uniloc("annotex"), 1 as i32); //self.annotation_tex as i32);
+            gl::Uniform1ui(self.uniloc("view_mode"), self.view_mode as u32);
+            if let Some(tex) = self.entropy_tex {
+                gl::ActiveTexture(gl::TEXTURE3);
+                gl::BindTexture(gl::TEXTURE_2D, tex);
+                gl::Uniform1i(self.uniloc("entropytex"), 3);
+                gl::Uniform1ui(self.uniloc("entropy_block"), self.entropy_block as u32);
+            }
             gl::Uniform2f(self.uniloc("ul_offset"), self.ul_offset.0, self.ul_offset.1);
             gl::Uniform1f(self.uniloc("zoom"),self.zoom);
+            let mut colors = [0.0 as GLfloat; 3*MAX_LAYERS];
+            for (i, layer) in self.layers.iter().enumerate() {
+                colors[3*i] = layer.color.0;
+                colors[3*i+1] = layer.color.1;
+                colors[3*i+2] = layer.color.2;
+            }
+            gl::Uniform3fv(self.uniloc("layer_colors"), MAX_LAYERS as GLsizei, colors.as_ptr());
 
             gl::BindVertexArray(self.vao);
             gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
         }        
         let bfc = self.byte_from_coords(self.mouse_state.last_pos);
+        // Right hand edge of the bitmap, which the status text is aligned to
+        let right = size.0 - self.dump_width();
         {
-            let text = match bfc {
-                Some(x) => format!("0x{:x} ({:x})",x,x%(self.word/8)),
+            let mut text = match bfc {
+                Some(x) if self.map.is_identity() => format!("0x{:x} ({:x})",x,x%(self.layout.word/8)),
+                Some(x) => format!("{} @0x{:x} ({:x})",self.map.format(x as usize),x,x%(self.layout.word/8)),
                 None => String::new(),
             };
+            let source = self.image.and_then(|image| image.source_of(bfc? as usize));
+            if let Some((src, offset)) = source {
+                let name = src.path.file_name().unwrap_or(src.path.as_os_str()).to_string_lossy();
+                text = format!("{}+0x{:x}  {}", name, offset, text);
+            }
             let text_sz = self.font.size(text.as_str());
-            let location = (size.0 - text_sz.0 as i32,
+            let location = (right - text_sz.0 as i32,
                            size.1 - text_sz.1 as i32);
             self.font.draw(size, location, text.as_str());
         }
         {
-            let status = format!("str 0x{:x}",self.word/8);
+            let status = format!("str 0x{:x} {}",self.layout.word/8,self.layout.byte_order.name());
             let text_sz = self.font.size(status.as_str());
-            let location = (size.0 - text_sz.0 as i32,
+            let location = (right - text_sz.0 as i32,
                            size.1 - 2*text_sz.1 as i32);
             self.font.draw(size, location, status.as_str());
         }
-        match bfc {
-            Some(x) => match self.annotation_store {
-                Some(ref store) => {
-                    let annos = store.query(x as usize);
-                    let y = 0;
-                    for a in annos {
-                        let s = a.comments();
-                        let location = (size.0.saturating_sub(self.font.width(s)), y);
-                        self.font.draw(size, location, s);
-                    }
-                },
-                None => {}
-            },
-            None => {}
+        if let Some(sel) = self.selection {
+            let mut status = format!("sel {}-{} ({} bytes)", self.map.format(sel.start),
+                                     self.map.format(sel.end), sel.len());
+            if let Some((n, total)) = self.search_match {
+                status = format!("match {}/{}  {}", n, total, status);
+            }
+            let text_sz = self.font.size(status.as_str());
+            let location = (right - text_sz.0 as i32,
+                           size.1 - 3*text_sz.1 as i32);
+            self.font.draw(size, location, status.as_str());
+        }
+        {
+            // Layer list, bottom of the z-order first
+            let mut y = size.1;
+            for (i, layer) in self.layers.iter().enumerate().rev() {
+                let line = format!("{}{} {} ({})",
+                                   if i == self.active_layer { '>' } else { ' ' },
+                                   if layer.visible { '*' } else { '-' },
+                                   layer.title(), layer.store.len());
+                y -= self.font.height(line.as_str());
+                self.font.draw(size, (0, y), line.as_str());
+            }
+        }
+        if let Some(x) = bfc {
+            let mut y = 0;
+            for (layer, a) in self.layers.query(x as usize) {
+                let s = format!("{}: {}", layer.title(), a.comments());
+                let location = (right.saturating_sub(self.font.width(s.as_str())), y);
+                self.font.draw(size, location, s.as_str());
+                y += self.font.height(s.as_str());
+            }
+        }
+        if self.show_dump {
+            self.draw_dump(size);
+            if self.show_inspector {
+                // Inspect the byte under the mouse, or else the one the dump follows.
+                self.draw_inspector(size, bfc.map_or(self.dump_cursor(), |b| b as usize));
+            }
+        }
+        if let Some(ref prompt) = self.prompt {
+            self.font.draw(size, (0, 0), prompt.display().as_str());
         }
         self.window.swap_buffers();
     }
@@ -315,15 +595,20 @@ impl<'a> Visualizer<'a> {
     }
 
     fn handle_scroll(&mut self, ydelta : f64) {
+        if self.in_dump(self.mouse_state.last_pos) {
+            self.scroll_dump(-3 * ydelta.round() as isize);
+            return;
+        }
         let z = self.zoom * (1.1 as f32).powf(ydelta as f32);
         let pos = self.mouse_state.last_pos;
         self.zoom_to_center(pos,if z >= 1.0 { z } else { 1.0 } );
     }
 
     fn update_annotations(&mut self) {
+        self.layers.paint(&mut self.annotation_d);
         let maxw : usize = 16384;
         let tw : usize = maxw;
-        let th : usize = (self.data_len + (maxw-1))/maxw;
+        let th : usize = (self.layout.data_len + (maxw-1))/maxw;
         let cloned_annot = self.annotation_d.clone();
         unsafe {
             gl::ActiveTexture(gl::TEXTURE1);
@@ -334,39 +619,548 @@ impl<'a> Visualizer<'a> {
         }
     }
 
+    // Build the texture behind the entropy heat map: one texel per block,
+    // holding the entropy in 32nds of a bit per byte.
+    fn build_entropy_texture(&mut self) {
+        use romexp::annotation::entropy::block_entropy;
+        let values = block_entropy(&self.dat, self.entropy_block);
+        let maxw : usize = 16384;
+        let tw : usize = maxw;
+        let th : usize = values.len().div_ceil(maxw).max(1);
+        let mut d : Vec<u8> = values.iter().map(|h| (h * 32.0).min(255.0) as u8).collect();
+        d.resize(tw*th, 0);
+        let mut tex : GLuint = 0;
+        unsafe {
+            gl::GenTextures(1, &mut tex);
+            gl::ActiveTexture(gl::TEXTURE3);
+            gl::BindTexture(gl::TEXTURE_2D, tex);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_BASE_LEVEL, 0);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, 0);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R8UI as GLint,
+                           tw as GLsizei, th as GLsizei, 0,
+                           gl::RED_INTEGER, gl::UNSIGNED_BYTE, d.as_ptr() as *const GLvoid);
+        }
+        self.entropy_tex = Some(tex);
+    }
+
+    fn add_layer(&mut self, store : annotation::AnnotationStore) {
+        match self.layers.add(store) {
+            Some(idx) => self.active_layer = idx,
+            None => println!("Too many annotation layers; delete one first."),
+        }
+        self.update_annotations();
+    }
+
+    /// Scroll so that the byte is in the centre of the window, and highlight it.
+    pub fn go_to(&mut self, idx : usize) {
+        match self.layout.coords_from_byte(idx) {
+            Some((x, y)) => {
+                let size = self.window.get_size();
+                let width = size.0 - self.dump_width();
+                let z = self.zoom as f64;
+                self.ul_offset = ((x * z + z / 2.0 - width as f64 / 2.0) as f32,
+                                  (y * z + z / 2.0 - size.1 as f64 / 2.0) as f32);
+                self.highlight = Some(idx);
+                self.dump_focus = idx;
+                self.dump_top = None;
+            },
+            None => println!("{} is outside the data shown", self.map.format(idx)),
+        }
+    }
+
+    // Width in pixels of the dump panel, or 0 when it's hidden.
+    fn dump_width(&self) -> i32 {
+        if self.show_dump { self.font.width(&" ".repeat(DUMP_CHARS)) } else { 0 }
+    }
+
+    fn in_dump(&self, pos : (f64, f64)) -> bool {
+        self.show_dump && pos.0 >= (self.window.get_size().0 - self.dump_width()) as f64
+    }
+
+    // The byte the dump follows: the start of the selection, or else the last one hovered.
+    fn dump_cursor(&self) -> usize {
+        self.selection.map_or(self.dump_focus, |s| s.start)
+    }
+
+    // First position shown in the dump; unless scrolled, the cursor's line
+    // sits a third of the way down.
+    fn dump_start(&self) -> usize {
+        match self.dump_top {
+            Some(top) => top,
+            None => {
+                let rows = self.dump_rows(self.window.get_size());
+                let line = self.dump_cursor() / DUMP_WIDTH;
+                line.saturating_sub(rows / 3) * DUMP_WIDTH
+            },
+        }
+    }
+
+    fn scroll_dump(&mut self, lines : isize) {
+        let last = self.layout.data_len.saturating_sub(1) / DUMP_WIDTH * DUMP_WIDTH;
+        let top = self.dump_start() as isize + lines * DUMP_WIDTH as isize;
+        self.dump_top = Some((top.max(0) as usize).min(last));
+    }
+
+    // Lines of the inspector, below the dump.
+    fn inspector_rows(&self) -> usize {
+        if self.show_inspector { inspect::inspect(&[0; 8], 0, &self.charset).len() + 2 } else { 0 }
+    }
+
+    // Lines of the dump that fit above the inspector.
+    fn dump_rows(&self, size : (i32, i32)) -> usize {
+        ((size.1 / self.font.height("0")) as usize).saturating_sub(self.inspector_rows())
+    }
+
+    // Hex and ASCII dump in a panel to the right of the bitmap, grouped by
+    // word and in the byte order shown. The cursor's line is marked with `>`
+    // and selected lines with `*`.
+    fn draw_dump(&self, size : (i32, i32)) {
+        let left = size.0 - self.dump_width();
+        let line_height = self.font.height("0");
+        let rows = self.dump_rows(size);
+        let word = (self.layout.word / 8) as usize;
+        let group = if word > 1 && word < DUMP_WIDTH { word } else { 0 };
+        let start = self.dump_start();
+        let cursor = self.dump_cursor();
+        let data = &self.dat[..self.layout.data_len];
+        let lines = export::dump_lines(data, self.layout.byte_order, start, rows, DUMP_WIDTH, group);
+        for (i, line) in lines.iter().enumerate() {
+            let pos = start + i * DUMP_WIDTH;
+            let end = pos + DUMP_WIDTH - 1;
+            let mark = if cursor >= pos && cursor <= end { '>' }
+                       else if self.selection.is_some_and(|s| s.start <= end && s.end >= pos) { '*' }
+                       else { ' ' };
+            let text = format!("{}{}", mark, line);
+            self.font.draw(size, (left + 4, i as i32 * line_height), text.as_str());
+        }
+    }
+
+    // The bytes at `pos` decoded as numbers, pointers and text, at the foot of the panel.
+    fn draw_inspector(&self, size : (i32, i32), pos : usize) {
+        let left = size.0 - self.dump_width() + 4;
+        let line_height = self.font.height("0");
+        let mut y = size.1 - self.inspector_rows() as i32 * line_height;
+        let data = &self.dat[..self.layout.data_len];
+        let title = format!("-- 0x{:x} --", pos);
+        y += line_height;
+        self.font.draw(size, (left, y), title.as_str());
+        for field in inspect::inspect(data, pos, &self.charset) {
+            y += line_height;
+            let line = format!("{:<2$}{}", field.name, field.value, INSPECT_NAME);
+            self.font.draw(size, (left, y), line.as_str());
+        }
+    }
+
+    // Select a byte and centre the view on it.
+    fn jump(&mut self, idx : usize) {
+        self.set_selection(Some(Selection::new(idx, idx)));
+        self.go_to(idx);
+    }
+
+    // Jump to where the pointer at the cursor points, remembering where we came
+    // from. Under the mouse, the pointer is taken to be aligned to its width.
+    fn follow_pointer(&mut self) {
+        let width = self.pointer.width;
+        let pos = match self.selection {
+            Some(sel) => sel.start,
+            None => match self.byte_from_coords(self.mouse_state.last_pos) {
+                Some(b) => b as usize / width * width,
+                None => return,
+            },
+        };
+        let data = &self.dat[..self.layout.data_len];
+        let pointer = match self.pointer.read(data, pos) {
+            Some(p) => p,
+            None => return,
+        };
+        let bank = self.map.address_of(pos).and_then(|a| a.1);
+        match self.pointer.target(pointer, data.len(), &self.map, bank) {
+            Some(target) => {
+                self.history.visit(pos);
+                self.jump(target);
+            },
+            None => println!("The pointer at {} (0x{:x}) points outside the image", self.map.format(pos), pointer),
+        }
+    }
+
+    // Open a prompt for an address, starting from the given value.
+    fn address_prompt(&mut self, label : &str, current : usize, action : PromptAction) {
+        let current = match action {
+            PromptAction::SelectLength => format!("0x{:x}", current),
+            _ => self.map.format(current),
+        };
+        let label = format!("{} (. is {})", label, current);
+        self.prompt = Some(Prompt::from_key(label.as_str(), "", action));
+    }
+
+    // Where address prompts start from: the selection, or else the byte under the mouse.
+    fn current_byte(&self) -> usize {
+        match self.selection {
+            Some(sel) => sel.start,
+            None => self.byte_from_coords(self.mouse_state.last_pos)
+                .map_or(self.layout.data_offset, |b| b as usize),
+        }
+    }
+
+    fn submit_address(&mut self, action : PromptAction, text : &str) {
+        let current = match action {
+            PromptAction::SelectEnd => self.selection.map_or(self.current_byte(), |s| s.end),
+            PromptAction::SelectLength => self.selection.map_or(1, |s| s.len()),
+            _ => self.current_byte(),
+        };
+        // Lengths are counts of bytes; everything else is an address.
+        let value = match action {
+            PromptAction::SelectLength => parse::eval_expr(text, current as u64).map(|v| v as usize),
+            _ => self.map.parse_address(text, current),
+        };
+        let value = match value {
+            Ok(v) => v,
+            Err(e) => { println!("{}", e); return; },
+        };
+        let sel = self.selection.unwrap_or(Selection::new(current, current));
+        match action {
+            PromptAction::GoTo => self.go_to(value),
+            PromptAction::SelectStart => {
+                self.set_selection(Some(Selection::new(value, sel.end.max(value))));
+                self.go_to(value);
+            },
+            PromptAction::SelectEnd => {
+                self.set_selection(Some(Selection::new(sel.start.min(value), value)));
+                self.go_to(value);
+            },
+            PromptAction::SelectLength if value > 0 => {
+                self.set_selection(Some(Selection::new(sel.start, sel.start + value - 1)));
+                self.go_to(sel.start + value - 1);
+            },
+            _ => println!("the selection must be at least one byte long"),
+        }
+    }
+
+    // Select and centre the first search match starting after `from`, or
+    // the last one before it, wrapping around at the ends.
+    fn next_match(&mut self, from : usize, forward : bool) {
+        let store = match self.layers.find(search::SEARCH_LAYER) {
+            Some(idx) => &self.layers.get(idx).unwrap().store,
+            None => { self.search_match = None; return; },
+        };
+        let hit = if forward {
+            store.next_after(from).or_else(|| store.iter().next().map(|a| a.as_ref()))
+        } else {
+            store.prev_before(from).or_else(|| store.iter().last().map(|a| a.as_ref()))
+        };
+        let (start, end) = match hit {
+            Some(a) => a.span(),
+            None => { self.search_match = None; return; },
+        };
+        let number = store.iter().take_while(|a| a.span().0 < start).count() + 1;
+        let total = store.len();
+        self.set_selection(Some(Selection::new(start, end - 1)));
+        self.search_match = Some((number, total));
+        self.go_to(start);
+    }
+
+    // Run an engine with its default settings, or with shift held, ask for parameters first.
+    fn start_engine(&mut self, id : &'static str, mods : glfw::Modifiers) {
+        if mods.contains(glfw::Modifiers::Shift) {
+            let engine = annotation::engine_by_id(id).unwrap();
+            let names : Vec<&str> = engine.parameters().iter().map(|p| p.name).collect();
+            let label = format!("{} parameters ({}=...)", id, names.join("=... "));
+            self.prompt = Some(Prompt::from_key(label.as_str(), "", PromptAction::RunEngine { id }));
+        } else {
+            self.run_engine(id, &annotation::Params::new());
+        }
+    }
+
+    // Run an engine and add its annotations as a layer. Progress is shown in
+    // the title bar, and holding Escape cancels the run.
+    fn run_engine(&mut self, id : &str, params : &annotation::Params) {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        let engine = annotation::engine_by_id(id).unwrap();
+        let cancel = AtomicBool::new(false);
+        let result = {
+            let window = &mut self.window;
+            let mut last_percent = None;
+            let mut report = |done : usize, total : usize| {
+                let percent = done * 100 / total.max(1);
+                if last_percent != Some(percent) {
+                    last_percent = Some(percent);
+                    window.set_title(&format!("ROM Explorer - {} {}% (Esc to cancel)", id, percent));
+                }
+                window.glfw.poll_events();
+                if window.get_key(glfw::Key::Escape) == glfw::Action::Press {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            };
+            let mut progress = annotation::Progress::new(&mut report, &cancel);
+            engine.build_annotations(&self.dat, params, &mut progress)
+        };
+        self.window.set_title("ROM Explorer");
+        match result {
+            Ok(store) => self.add_layer(store),
+            Err(annotation::EngineError::Cancelled) => {
+                // Drop the input that arrived meanwhile, so that the Escape
+                // doesn't go on to close the window.
+                for _ in glfw::flush_messages(&self.events) {}
+                println!("{}: cancelled", id);
+            },
+            Err(e) => println!("{}: {}", id, e),
+        }
+    }
+
+    fn user_layer_mut(&mut self) -> &mut annotation::AnnotationStore {
+        let idx = match self.layers.find(user::USER_LAYER) {
+            Some(idx) => idx,
+            None => self.layers.add(annotation::AnnotationStore::new(user::USER_LAYER))
+                .expect("no room for the user annotation layer"),
+        };
+        &mut self.layers.get_mut(idx).unwrap().store
+    }
+
+    fn submit_prompt(&mut self, prompt : Prompt<PromptAction>) {
+        match prompt.action {
+            PromptAction::Annotate { span } => {
+                let (kind, comments) = user::parse_entry(prompt.text.as_str());
+                self.user_layer_mut().insert(Box::new(user::make(kind, span, comments)));
+            },
+            PromptAction::EditAnnotation { span } => {
+                let store = self.user_layer_mut();
+                store.remove(span);
+                if !prompt.text.trim().is_empty() {
+                    let (kind, comments) = user::parse_entry(prompt.text.as_str());
+                    store.insert(Box::new(user::make(kind, span, comments)));
+                }
+            },
+            PromptAction::GoTo | PromptAction::SelectStart | PromptAction::SelectEnd |
+            PromptAction::SelectLength => self.submit_address(prompt.action, prompt.text.as_str()),
+            PromptAction::Export { selection, order } => {
+                let data = export::selected_data(&self.dat, selection, order);
+                let path = prompt.text.trim();
+                let address = self.map.address_of(selection.start).map_or(selection.start as u64, |a| a.0);
+                match export::export_file(path, &data, address, None) {
+                    Ok(format) => println!("Wrote {} bytes to {} ({:?})", data.len(), path, format),
+                    Err(e) => println!("Could not export to {}: {}", path, e),
+                }
+            },
+            PromptAction::Search => {
+                match search::search(&self.dat, prompt.text.as_str()) {
+                    Ok(store) => {
+                        let empty = store.is_empty();
+                        self.add_layer(store);
+                        if empty { println!("No matches"); }
+                        // The first match at or after the current byte; from 0,
+                        // this wraps around to the first match of all.
+                        let from = self.current_byte().wrapping_sub(1);
+                        self.next_match(from, true);
+                    },
+                    Err(e) => println!("Bad search: {}", e),
+                }
+            },
+            PromptAction::CustomByteOrder => {
+                match ByteOrder::from_name(prompt.text.trim()) {
+                    Ok(order) => self.set_byte_order(order),
+                    Err(e) => println!("{}", e),
+                }
+            },
+            PromptAction::Transform => {
+                match Pipeline::parse(prompt.text.as_str()) {
+                    Ok(transform) => self.set_transform(transform),
+                    Err(e) => println!("{}", e),
+                }
+            },
+            PromptAction::PointerFormat => {
+                match PointerFormat::parse(prompt.text.as_str()) {
+                    Ok(pointer) => self.pointer = pointer,
+                    Err(e) => println!("{}", e),
+                }
+            },
+            PromptAction::RunEngine { id } => {
+                match annotation::Params::parse(prompt.text.as_str()) {
+                    Ok(params) => self.run_engine(id, &params),
+                    Err(e) => println!("{}: {}", id, e),
+                }
+            },
+        }
+        self.update_annotations();
+    }
+
+    // Keyboard input while the prompt is open
+    fn handle_prompt_kb(&mut self, key : glfw::Key) {
+        use glfw::Key::*;
+        match key {
+            Enter | KpEnter => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.submit_prompt(prompt);
+                }
+            },
+            Escape => self.prompt = None,
+            Backspace => if let Some(ref mut p) = self.prompt { p.backspace() },
+            _ => (),
+        }
+    }
+
     // Handle keyboard input
-    fn handle_kb(&mut self, key : glfw::Key) {
+    fn handle_kb(&mut self, key : glfw::Key, mods : glfw::Modifiers) {
         use glfw::Key::*;
+        if self.prompt.is_some() {
+            self.handle_prompt_kb(key);
+            return;
+        }
         match key {
-            Num1 => self.bpp = 1,
-            Num2 => self.bpp = 2,
-            Num4 => self.bpp = 4,
-            Num8 => self.bpp = 8,
+            Num1 => self.layout.bpp = 1,
+            Num2 => self.layout.bpp = 2,
+            Num4 => self.layout.bpp = 4,
+            Num8 => self.layout.bpp = 8,
 
             Escape => self.window.set_should_close(true),
-            Up => self.zoom_in(),
-            Down => self.zoom_out(),
-            Right => {
-                let s = self.word + 8;
+            Up if mods.contains(glfw::Modifiers::Control) => self.zoom_in(),
+            Down if mods.contains(glfw::Modifiers::Control) => self.zoom_out(),
+            Left | Right | Up | Down => {
+                let row = (self.layout.word / 8) as isize;
+                let delta = match key { Left => -1, Right => 1, Up => -row, _ => row };
+                self.move_selection(delta, mods.contains(glfw::Modifiers::Shift));
+            },
+            Equal | KpAdd => {
+                let s = self.layout.word + 8;
                 self.set_word(s);
             },
-            Left => {
-                let s = self.word - 8;
+            Minus | KpSubtract => {
+                let s = self.layout.word - 8;
                 self.set_word(if s < 8 { 8 } else { s });
             },
+            A if mods.contains(glfw::Modifiers::Control) => {
+                self.set_selection(Some(Selection::all(self.layout.data_len)));
+            },
+            R | C => {
+                // Widen the selection to whole words, or whole columns
+                let word = (self.layout.word / 8) as usize;
+                let (unit, origin) = if key == R { (word, 0) }
+                                     else { (word * self.layout.col_height as usize, self.layout.data_offset) };
+                let limit = self.layout.data_len;
+                let snapped = self.selection.map(|s| s.snap(unit, origin, limit));
+                self.set_selection(snapped);
+            },
+            Enter | KpEnter => self.select_annotation_at(self.mouse_state.last_pos),
+            Slash => {
+                let label = "Search (hex bytes with ?? wildcards, \"text\" or /regex/)";
+                self.prompt = Some(Prompt::from_key(label, "", PromptAction::Search));
+            },
+            N => {
+                // Step on from the selected match
+                let from = self.selection.map_or(self.current_byte(), |s| s.start);
+                self.next_match(from, !mods.contains(glfw::Modifiers::Shift));
+            },
+            X => {
+                // Export the selection, or else the annotation under the mouse;
+                // with shift, in the byte order shown.
+                if self.selection.is_none() {
+                    self.select_annotation_at(self.mouse_state.last_pos);
+                }
+                if let Some(selection) = self.selection {
+                    let order = if mods.contains(glfw::Modifiers::Shift) { Some(self.layout.byte_order) } else { None };
+                    let label = format!("Export {}-{}{} to (.bin .c .hex .srec .txt)",
+                                        self.map.format(selection.start), self.map.format(selection.end),
+                                        order.map_or(String::new(), |o| format!(" as {}", o.name())));
+                    self.prompt = Some(Prompt::from_key(label.as_str(), "", PromptAction::Export { selection, order }));
+                }
+            },
+            GraveAccent if mods.contains(glfw::Modifiers::Shift) => {
+                let current = self.custom_order.map_or(String::new(), |o| o.name());
+                let label = "Byte order (stored byte at each position, e.g. 1032)";
+                self.prompt = Some(Prompt::from_key(label, current.as_str(), PromptAction::CustomByteOrder));
+            },
             GraveAccent => {
-                self.swap_endian = !self.swap_endian;
-            },
-            S => {
-                use annotation::AnnotationEngine;
-                let engine = annotation::CStringAnnotationEngine::new();
-                let annotations = engine.build_annotations(self.dat);
-                for annotation in annotations.iter() {
-                    for n in annotation.span().0 .. annotation.span().1 {
-                        self.annotation_d[n] = 0x66;
+                let order = self.layout.byte_order.next(self.custom_order);
+                self.layout.byte_order = order;
+            },
+            S => self.start_engine("strings", mods),
+            E => self.start_engine("entropy", mods),
+            F => self.start_engine("fill", mods),
+            O => self.start_engine("container", mods),
+            M => self.start_engine("magic", mods),
+            G => {
+                let current = self.current_byte();
+                self.address_prompt("Go to", current, PromptAction::GoTo);
+            },
+            W if mods.contains(glfw::Modifiers::Shift) => {
+                let current = self.selection.map_or(self.current_byte(), |s| s.end);
+                self.address_prompt("Selection end", current, PromptAction::SelectEnd);
+            },
+            W => {
+                let current = self.current_byte();
+                self.address_prompt("Selection start", current, PromptAction::SelectStart);
+            },
+            L => {
+                let current = self.selection.map_or(1, |s| s.len());
+                self.address_prompt("Selection length", current, PromptAction::SelectLength);
+            },
+            D if mods.contains(glfw::Modifiers::Shift) => self.dump_top = None,
+            D => self.show_dump = !self.show_dump,
+            I => self.show_inspector = !self.show_inspector,
+            P if mods.contains(glfw::Modifiers::Shift) => {
+                let current = self.pointer.to_string();
+                let label = "Pointers (width=1..8 order=le/be base=load address)";
+                self.prompt = Some(Prompt::from_key(label, current.as_str(), PromptAction::PointerFormat));
+            },
+            P => self.follow_pointer(),
+            T => {
+                let current = self.transform.to_string();
+                let label = "Transform (bits=76543210 addr=1,0 xor=ff nibble deinterleave=2)";
+                self.prompt = Some(Prompt::from_key(label, current.as_str(), PromptAction::Transform));
+            },
+            LeftBracket => {
+                let current = self.current_byte();
+                if let Some(to) = self.history.back(current) { self.jump(to); }
+            },
+            RightBracket => {
+                let current = self.current_byte();
+                if let Some(to) = self.history.forward(current) { self.jump(to); }
+            },
+            K => {
+                // Cycle through the built in charsets
+                let current = CHARSET_NAMES.iter().position(|&n| n.eq_ignore_ascii_case(self.charset.name()));
+                let next = current.map_or(0, |i| (i + 1) % CHARSET_NAMES.len());
+                self.charset = Charset::by_name(CHARSET_NAMES[next]).unwrap();
+            },
+            H => {
+                if self.view_mode == ViewMode::Raw {
+                    if self.entropy_tex.is_none() {
+                        self.build_entropy_texture();
                     }
+                    self.view_mode = ViewMode::Entropy;
+                } else {
+                    self.view_mode = ViewMode::Raw;
                 }
-                self.annotation_store = Some(annotations);
+            },
+            A => {
+                if let Some(Selection { start, end }) = self.selection {
+                    let label = format!("Annotate {}-{} (code/data/text/graphics/padding/unknown) comment",
+                                        self.map.format(start), self.map.format(end));
+                    let action = PromptAction::Annotate { span : (start, end + 1) };
+                    self.prompt = Some(Prompt::from_key(label.as_str(), "", action));
+                }
+            },
+            Tab if !self.layers.is_empty() => {
+                self.active_layer = (self.active_layer + 1) % self.layers.len();
+            },
+            V => {
+                if let Some(layer) = self.layers.get_mut(self.active_layer) {
+                    layer.visible = !layer.visible;
+                }
+                self.update_annotations();
+            },
+            PageUp => {
+                self.active_layer = self.layers.raise(self.active_layer);
+                self.update_annotations();
+            },
+            PageDown => {
+                self.active_layer = self.layers.lower(self.active_layer);
+                self.update_annotations();
+            },
+            Delete if self.active_layer < self.layers.len() => {
+                self.layers.remove(self.active_layer);
+                self.active_layer = self.active_layer.saturating_sub(1);
                 self.update_annotations();
             },
             _ => (),
@@ -376,6 +1170,9 @@ impl<'a> Visualizer<'a> {
     fn handle_mouse_move(&mut self, pos : (f64, f64) ) {
         if self.mouse_state.last_pos != pos { self.mouse_state.moved = true; }
         self.mouse_state.last_pos = pos;
+        if let Some(idx) = self.byte_from_coords(pos) {
+            self.dump_focus = idx as usize;
+        }
         match self.mouse_state.op {
             MouseDragOp::Panning { original_ul, start } => {
                 let (x1, y1) = start;
@@ -389,7 +1186,7 @@ impl<'a> Visualizer<'a> {
                 let drag_end = self.byte_from_coords(self.mouse_state.last_pos);
                 let drag_start = self.byte_from_coords(start);
                 match (drag_start, drag_end) {
-                    (Some(s), Some(e)) => self.set_selection(s * 8, e * 8), // *8 because bit index
+                    (Some(s), Some(e)) => self.set_selection(Some(Selection::new(s as usize, e as usize))),
                     _ => {},
                 };
             },
@@ -398,27 +1195,52 @@ impl<'a> Visualizer<'a> {
     }
     
     fn byte_from_coords(&self, pos : (f64, f64) ) -> Option<u32> {
+        if self.in_dump(pos) { return None; }
         // find (possibly off-screen) location of 0,0 in data.
         // adjust for zoom
         let (x, y) = ((pos.0 + self.ul_offset.0 as f64)/self.zoom as f64,
                       (pos.1 + self.ul_offset.1 as f64)/self.zoom as f64);
-        // add deltas to upper left corner of image
-        
-        if x < 0.0 || y < 0.0 || y >= self.col_height as f64
-        {
-            None
-        } else {
-            let column = x as u32/(self.word + self.spacing);
-            let row = y as u32;
-            let el_in_row = x as u32 % (self.word + self.spacing);
+        self.layout.byte_from_coords((x, y)).map(|idx| idx as u32)
+    }
 
-            let el_per_b = (8 / self.bpp) as u32;
-            let cw_in_b = self.word / (self.bpp as u32);
+    // Arrow keys move the start of the selection, or with shift, the end;
+    // with nothing selected they start from the byte under the mouse.
+    fn move_selection(&mut self, delta : isize, end : bool) {
+        let limit = self.layout.data_len;
+        let sel = match self.selection {
+            Some(sel) if end => sel.move_end(delta, limit),
+            Some(sel) => sel.move_start(delta, limit),
+            None => { let b = self.current_byte(); Selection::new(b, b) },
+        };
+        self.set_selection(Some(sel));
+    }
 
-            let el_idx = (cw_in_b * self.col_height * column) + (row * cw_in_b) + el_in_row;
-            let idx = el_idx / el_per_b;
+    // Select the topmost visible annotation under the mouse.
+    fn select_annotation_at(&mut self, pos : (f64, f64)) {
+        let idx = match self.byte_from_coords(pos) {
+            Some(idx) => idx as usize,
+            None => return,
+        };
+        let span = self.layers.query(idx).first().map(|&(_, a)| a.span());
+        if let Some((start, end)) = span {
+            self.set_selection(Some(Selection::new(start, end.saturating_sub(1).max(start))));
+        }
+    }
 
-            if idx < self.data_len as u32 { Some(idx) } else { None }
+    // Clicking on a user annotation selects it and opens it for editing.
+    fn edit_user_annotation_at(&mut self, pos : (f64, f64)) {
+        let idx = match (self.byte_from_coords(pos), self.layers.find(user::USER_LAYER)) {
+            (Some(idx), Some(layer)) if self.layers.get(layer).unwrap().visible => (idx as usize, layer),
+            _ => return,
+        };
+        let hit = self.layers.get(idx.1).unwrap().store.query(idx.0).first()
+            .map(|a| (a.span(), user::entry_text(a.type_str(), a.comments())));
+        if let Some((span, text)) = hit {
+            self.set_selection(Some(Selection::new(span.0, span.1.saturating_sub(1))));
+            let label = format!("Edit {}-{} (clear to delete)", self.map.format(span.0),
+                                self.map.format(span.1.saturating_sub(1)));
+            let action = PromptAction::EditAnnotation { span };
+            self.prompt = Some(Prompt::from_click(label.as_str(), text.as_str(), action));
         }
     }
 
@@ -429,6 +1251,7 @@ impl<'a> Visualizer<'a> {
                 self.mouse_state.last_pos = self.window.get_cursor_pos();
                 self.mouse_state.op = 
                     match (button, modifiers) {
+                        _ if self.in_dump(self.mouse_state.last_pos) => MouseDragOp::NoOp,
                         (glfw::MouseButtonLeft,glfw::Modifiers::Shift) |
                         (glfw::MouseButtonMiddle,_) => MouseDragOp::Panning {
                             original_ul : self.ul_offset,
@@ -441,8 +1264,10 @@ impl<'a> Visualizer<'a> {
             },
             glfw::Action::Release => {
                 match self.mouse_state.op {
-                    MouseDragOp::Select { start } if !self.mouse_state.moved => {
-                        self.set_selection(0,0);
+                    MouseDragOp::Select { .. } if !self.mouse_state.moved => {
+                        self.set_selection(None);
+                        self.highlight = None;
+                        self.edit_user_annotation_at(self.mouse_state.last_pos);
                     },
                     _ => {},
                 }
@@ -457,13 +1282,14 @@ impl<'a> Visualizer<'a> {
        loop {
            match self.events.try_recv() {
                Ok((_, event)) => match event {
-                   glfw::WindowEvent::Key(key, _, Action::Press, _) => self.handle_kb(key),
-                   glfw::WindowEvent::Key(key, _, Action::Repeat, _) => self.handle_kb(key),
+                   glfw::WindowEvent::Key(key, _, Action::Press, m) => self.handle_kb(key, m),
+                   glfw::WindowEvent::Key(key, _, Action::Repeat, m) => self.handle_kb(key, m),
+                   glfw::WindowEvent::Char(c) => if let Some(ref mut p) = self.prompt { p.push_char(c) },
                    glfw::WindowEvent::MouseButton(b, a, m) => self.handle_mouse_button(b,a,m),
                    glfw::WindowEvent::CursorPos(x,y) => self.handle_mouse_move((x,y)),
                    glfw::WindowEvent::Scroll(_, ydelta) => self.handle_scroll(ydelta),
                    glfw::WindowEvent::Size(x,y) => {
-                       //self.col_height = y as u32;
+                       //self.layout.col_height = y as u32;
                        unsafe { gl::Viewport(0,0,x,y); }
                    },
 